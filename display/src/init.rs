@@ -47,6 +47,14 @@ pub fn terrain_brushes() -> VecMap<Rc<Brush>> {
     ret.insert(Corridor as usize, Builder::new("assets/floors.png").color(SLATEGRAY).tile(32, 0).finish());
     ret.insert(OpenDoor as usize, Builder::new("assets/walls.png").color(SADDLEBROWN).wall(128, 0, 160, 0).finish());
     ret.insert(Grass2 as usize, Builder::new("assets/floors.png").color(DARKGREEN).tile(64, 0).finish());
+    ret.insert(LockedDoor as usize, Builder::new("assets/walls.png")
+               .color(DARKGOLDENROD).wall(128, 0, 160, 0)
+               .color(LIGHTSLATEGRAY).wall(0, 0, 96, 0).finish());
+    ret.insert(Ash as usize, Builder::new("assets/floors.png").color(DIMGRAY).tile(64, 0).finish());
+    ret.insert(Ice as usize, Builder::new("assets/floors.png").colors(LIGHTCYAN, LIGHTSTEELBLUE).tile(96, 0).finish());
+    ret.insert(ShallowWater as usize, Builder::new("assets/floors.png").colors(ROYALBLUE, STEELBLUE).tile(96, 0).finish());
+    ret.insert(SecretDoor as usize, Builder::new("assets/walls.png").color(LIGHTSLATEGRAY).wall(0, 0, 32, 0).finish());
+    ret.insert(Rubble as usize, Builder::new("assets/props.png").color(DARKGOLDENROD).tile(96, 0).finish());
 
     ret
 }
@@ -60,12 +68,29 @@ pub fn entity_brushes() -> VecMap<Rc<Brush>> {
     ret.insert(Snake as usize, Builder::new("assets/mobs.png").color(GREEN).mob(32, 0).finish());
     ret.insert(Dreg as usize, Builder::new("assets/mobs.png").color(OLIVE).mob(64, 0).finish());
     ret.insert(Ogre as usize, Builder::new("assets/mobs.png").color(DARKCYAN).mob(96, 0).finish());
+    ret.insert(Wraith as usize, Builder::new("assets/mobs.png").color(LAVENDER).mob(0, 32).finish());
+    ret.insert(Salamander as usize, Builder::new("assets/mobs.png").color(ORANGERED).mob(64, 32).finish());
+    ret.insert(Slime as usize, Builder::new("assets/mobs.png").color(YELLOW).mob(224, 0).finish());
 
     ret.insert(Sword as usize, Builder::new("assets/props.png").color(WHITE).tile(128, 32).finish());
+    ret.insert(Torch as usize, Builder::new("assets/props.png").color(ORANGE).tile(224, 0).finish());
+    ret.insert(Helmet as usize, Builder::new("assets/props.png").color(SILVER).tile(160, 32).finish());
+    ret.insert(Armor as usize, Builder::new("assets/props.png").color(SADDLEBROWN).tile(64, 64).finish());
 
     ret.insert(Scroll1 as usize, Builder::new("assets/props.png").color(WHITE).tile(224, 64).finish());
+    ret.insert(Tome as usize, Builder::new("assets/props.png").color(ROYALBLUE).tile(224, 64).finish());
+    ret.insert(Potion1 as usize, Builder::new("assets/props.png").color(HOTPINK).tile(192, 32).finish());
+    ret.insert(Potion2 as usize, Builder::new("assets/props.png").color(MEDIUMPURPLE).tile(192, 32).finish());
     ret.insert(Wand1 as usize, Builder::new("assets/props.png").color(RED).tile(224, 32).finish());
     ret.insert(Wand2 as usize, Builder::new("assets/props.png").color(CYAN).tile(224, 32).finish());
+    ret.insert(Wand3 as usize, Builder::new("assets/props.png").color(LIGHTSTEELBLUE).tile(224, 32).finish());
+    ret.insert(Wand4 as usize, Builder::new("assets/props.png").color(YELLOWGREEN).tile(224, 32).finish());
+    ret.insert(Key as usize, Builder::new("assets/props.png").color(GOLD).tile(96, 64).finish());
+    ret.insert(Trap as usize, Builder::new("assets/props.png").color(ORANGERED).tile(64, 0).finish());
+    ret.insert(Bag as usize, Builder::new("assets/props.png").color(PERU).tile(192, 64).finish());
+    ret.insert(Chest as usize, Builder::new("assets/props.png").color(SADDLEBROWN).tile(160, 0).finish());
+    ret.insert(Corpse as usize, Builder::new("assets/props.png").color(DARKRED).tile(96, 0).finish());
+    ret.insert(HealthOrb as usize, Builder::new("assets/props.png").color(CRIMSON).tile(192, 32).finish());
     ret
 }
 
@@ -81,6 +106,9 @@ pub fn misc_brushes() -> VecMap<Rc<Brush>> {
     ret.insert(HealthPip as usize, Builder::new("assets/gui.png").color(LIMEGREEN).rect(0, 8, 4, 4).finish());
     ret.insert(DarkHealthPip as usize, Builder::new("assets/gui.png").color(DARKSLATEGRAY).rect(0, 8, 4, 4).finish());
     ret.insert(BlockedOffSectorCell as usize, Builder::new("assets/floors.png").color(LIGHTGRAY).tile(0, 32).finish());
+    ret.insert(Fire as usize, Builder::new("assets/floors.png").color(Rgba::from_str("#f80a").unwrap()).tile(96, 0).finish());
+    ret.insert(Smoke as usize, Builder::new("assets/floors.png").color(Rgba::from_str("#666c").unwrap()).tile(96, 0).finish());
+    ret.insert(Gas as usize, Builder::new("assets/floors.png").color(Rgba::from_str("#8f48").unwrap()).tile(96, 0).finish());
 
     ret
 }
@@ -39,4 +39,7 @@ pub enum Icon {
     HealthPip,
     DarkHealthPip,
     BlockedOffSectorCell,
+    Fire,
+    Smoke,
+    Gas,
 }
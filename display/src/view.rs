@@ -12,7 +12,7 @@ use sprite::Sprite;
 use std::collections::HashMap;
 use std::iter::FromIterator;
 use std::rc::Rc;
-use world::{FovStatus, Location, Query, Sector, TerrainQuery, World};
+use world::{CloudKind, FovStatus, Location, Query, Sector, TerrainQuery, World};
 
 /// Useful general constant for cell dimension ops.
 pub static PIXEL_UNIT: f32 = 16.0;
@@ -23,6 +23,8 @@ pub struct WorldView {
     camera_loc: Location,
     screen_area: Rect<f32>,
     fov: Option<HashMap<Vector2D<i32>, Vec<Location>>>,
+    /// World map revision the screen FOV was computed for.
+    map_revision: u64,
 
     /// Mostly used in mapedit
     pub highlight_offscreen_tiles: bool,
@@ -36,19 +38,42 @@ impl WorldView {
             camera_loc: camera_loc,
             screen_area: screen_area,
             fov: None,
+            map_revision: 0,
             highlight_offscreen_tiles: false,
         }
     }
 
+    /// Center the view on the sector of the location.
     pub fn set_camera(&mut self, loc: Location) {
+        // The camera always snaps to sector center, so moving within a sector keeps the screen
+        // FOV.
+        let loc = loc.sector().center();
         if loc != self.camera_loc {
             self.camera_loc = loc;
             self.fov = None;
         }
     }
 
+    pub fn set_screen_area(&mut self, screen_area: Rect<f32>) {
+        if screen_area != self.screen_area {
+            self.screen_area = screen_area;
+            self.fov = None;
+        }
+    }
+
+    /// Force the screen FOV to be recomputed.
+    ///
+    /// Changes to the world map are noticed automatically, this needs to be called when the view
+    /// is used with a different world.
+    pub fn invalidate_fov(&mut self) { self.fov = None; }
+
     /// Recompute the cached screen view if the cache has been invalidated.
     fn ensure_fov(&mut self, world: &World) {
+        if world.map_revision() != self.map_revision {
+            self.map_revision = world.map_revision();
+            self.fov = None;
+        }
+
         if self.fov.is_none() {
             // Chart area, center in origin, inflated by tile width in every direction to get the cells
             // partially on screen included.
@@ -64,7 +89,10 @@ impl WorldView {
 
     pub fn draw<C: MagogContext>(&mut self, world: &World, context: &mut C) {
         let current_sector = self.camera_loc.sector();
-        self.camera_loc = current_sector.center();
+        if self.camera_loc != current_sector.center() {
+            self.camera_loc = current_sector.center();
+            self.fov = None;
+        }
 
         self.ensure_fov(world);
 
@@ -91,16 +119,7 @@ impl WorldView {
                 // Only accept map memory from the base layer (top of origins stack)
                 loc = origins[origins.len() - 1] + chart_pos;
 
-                let mut gate_point = false;
-                // Special case for terrain immediately below a portal when the portal destination
-                // is in map memory, it should be drawn as a gate icon in map memory.
-                if let Some(endpoint) = world.portal(loc) {
-                    if get_fov(world, endpoint) == Some(FovStatus::Remembered) {
-                        gate_point = true;
-                    }
-                }
-
-                if !gate_point && get_fov(world, loc) != Some(FovStatus::Remembered) {
+                if get_fov(world, loc) != Some(FovStatus::Remembered) {
                     // Bail out if there's no memory
                     continue;
                 }
@@ -122,7 +141,9 @@ impl WorldView {
                 });
             }
 
-            // TODO: Set up dynamic lighting, shade sprites based on angle and local light.
+            let light = world.light_level(loc);
+
+            // TODO: Shade sprites based on angle as well as local light.
             render::draw_terrain_sprites(world, loc, |layer, _angle, brush, frame_idx| {
                 sprites.push(Sprite {
                     layer: layer,
@@ -130,16 +151,19 @@ impl WorldView {
                     brush: if in_map_memory {
                         map_memory_colorize(Rc::clone(brush))
                     } else {
-                        Rc::clone(brush)
+                        light_colorize(Rc::clone(brush), light)
                     },
                     frame_idx: frame_idx,
                 })
             });
 
             // Draw entities in directly seen cells
-            // TODO: Items should be drawn even in map memory
             if !in_map_memory {
                 for &i in &world.entities_at(loc) {
+                    if world.is_trap(i) && !world.player_knows_trap(loc) {
+                        // Hidden trap.
+                        continue;
+                    }
                     if let Some(desc) = world.ecs().desc.get(i) {
                         let layer = if world.is_mob(i) {
                             Layer::Object
@@ -154,13 +178,57 @@ impl WorldView {
                         sprites.push(Sprite {
                             layer: layer,
                             offset: [screen_pos.x as i32, screen_pos.y as i32],
-                            brush: cache::entity(desc.icon),
+                            brush: light_colorize(cache::entity(desc.icon), light),
                             frame_idx: frame_idx,
                         });
 
                         draw_health_pips(&mut sprites, world, i, screen_pos);
                     }
                 }
+
+                if let Some(cloud) = world.cloud(loc) {
+                    sprites.push(Sprite {
+                        layer: Layer::Effect,
+                        offset: [screen_pos.x as i32, screen_pos.y as i32],
+                        brush: cache::misc(match cloud.kind {
+                            CloudKind::Fire => Icon::Fire,
+                            CloudKind::Smoke => Icon::Smoke,
+                            CloudKind::Gas => Icon::Gas,
+                        }),
+                        frame_idx: 0,
+                    });
+                }
+            }
+
+            // Draw what was last seen at remembered cells.
+            if in_map_memory {
+                let memory = world.player().and_then(|p| world.ecs().map_memory.get(p));
+                if let Some(seen) = memory.and_then(|m| m.entities.get(&loc)) {
+                    for appearance in seen {
+                        sprites.push(Sprite {
+                            layer: if appearance.is_mob {
+                                Layer::Object
+                            } else {
+                                Layer::Items
+                            },
+                            offset: [screen_pos.x as i32, screen_pos.y as i32],
+                            brush: map_memory_colorize(cache::entity(appearance.icon)),
+                            frame_idx: 0,
+                        });
+                    }
+                }
+            }
+
+            // Found traps stay in map memory.
+            if in_map_memory && world.player_knows_trap(loc) {
+                if let Some(desc) = world.trap_at(loc).and_then(|t| world.ecs().desc.get(t)) {
+                    sprites.push(Sprite {
+                        layer: Layer::Items,
+                        offset: [screen_pos.x as i32, screen_pos.y as i32],
+                        brush: map_memory_colorize(cache::entity(desc.icon)),
+                        frame_idx: 0,
+                    });
+                }
             }
 
             // XXX: This doesn't belong here, figure out a better place for debug visualizations
@@ -218,6 +286,30 @@ impl WorldView {
             }
         }
 
+        /// Darken brush in dimly lit cells.
+        fn light_colorize(brush: Rc<Vec<Frame>>, light: u32) -> Rc<Vec<Frame>> {
+            use std::ops::Deref;
+
+            // Light levels at or above this are drawn at full brightness.
+            const BRIGHT: u32 = 4;
+            if light >= BRIGHT {
+                return brush;
+            }
+
+            let brightness = 0.3 + 0.7 * light as f32 / BRIGHT as f32;
+            let mut ret: Vec<Frame> = brush.deref().clone();
+            for frame in &mut ret {
+                for splat in frame.iter_mut() {
+                    for i in 0..3 {
+                        splat.color[i] *= brightness;
+                        splat.back_color[i] *= brightness;
+                    }
+                }
+            }
+
+            Rc::new(ret)
+        }
+
         fn map_memory_colorize(brush: Rc<Vec<Frame>>) -> Rc<Vec<Frame>> {
             // XXX: This is horribly wasteful memory churning.
             use std::ops::Deref;
@@ -11,6 +11,7 @@ use world::{Command, CommandResult, Event, ItemType, Location, Query, Slot, Worl
 enum State {
     Main,
     Inventory(InventoryAction),
+    Container,
     Console,
     Aim(AimAction),
 }
@@ -20,27 +21,35 @@ enum InventoryAction {
     Drop,
     Equip,
     Use,
+    Store,
+    Throw,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum AimAction {
     Zap(Slot),
+    Throw(Slot),
+    Cast(Slot),
+    CloseDoor,
     // Maybe add intrinsic abilities not tied to a specific entity later
 }
 
 pub struct GameLoop {
     pub world: World,
     pub console: display::Console,
-    camera_loc: Location,
+    view: display::WorldView,
     state: State,
 }
 
 impl GameLoop {
     pub fn new(world: World) -> GameLoop {
+        let mut view = display::WorldView::new(Location::new(0, 0, 0), Rect::zero());
+        view.show_cursor = true;
+
         GameLoop {
             world,
             console: display::Console::default(),
-            camera_loc: Location::new(0, 0, 0),
+            view,
             state: State::Main,
         }
     }
@@ -91,7 +100,37 @@ impl GameLoop {
                 self.enter_state(State::Inventory(InventoryAction::Use));
                 Ok(Vec::new())
             }
+            C => {
+                self.enter_state(State::Aim(AimAction::CloseDoor));
+                Ok(Vec::new())
+            }
+            T => {
+                self.enter_state(State::Inventory(InventoryAction::Throw));
+                Ok(Vec::new())
+            }
+            P => {
+                self.enter_state(State::Inventory(InventoryAction::Store));
+                Ok(Vec::new())
+            }
+            O => {
+                let player = self.world.player().ok_or(())?;
+                if self.world.reachable_container(player).is_some() {
+                    self.enter_state(State::Container);
+                } else {
+                    let _ = writeln!(&mut self.console, "There's no container here.");
+                }
+                Ok(Vec::new())
+            }
+            Num1 => self.cast(Slot::Spell1),
+            Num2 => self.cast(Slot::Spell2),
+            Num3 => self.cast(Slot::Spell3),
+            Num4 => self.cast(Slot::Spell4),
+            Num5 => self.cast(Slot::Spell5),
+            Num6 => self.cast(Slot::Spell6),
+            Num7 => self.cast(Slot::Spell7),
+            Num8 => self.cast(Slot::Spell8),
             G => self.world.take(),
+            X => self.world.search(),
             Space => self.world.pass(),
             F5 => {
                 self.world
@@ -100,29 +139,63 @@ impl GameLoop {
                 Ok(Vec::new())
             }
             F9 => {
-                let mut savefile = File::open("save.gam").unwrap();
-                self.world = World::load(&mut savefile).unwrap();
+                match File::open("save.gam") {
+                    Ok(mut savefile) => {
+                        match World::load(&mut savefile) {
+                            Ok(world) => {
+                                self.world = world;
+                                self.view.invalidate_fov();
+                            }
+                            Err(e) => {
+                                let _ = writeln!(&mut self.console, "Unable to load game: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let _ = writeln!(&mut self.console, "Unable to open save file: {}", e);
+                    }
+                }
                 Ok(Vec::new())
             }
             _ => Ok(Vec::new()),
         }
     }
 
-    fn zap(&mut self, slot: Slot, dir: Dir6) -> CommandResult {
-        let ret = self.world.zap_item(slot, dir)?;
+    /// Cast the spell in a spell slot, aiming first if the spell needs a direction.
+    fn cast(&mut self, slot: Slot) -> CommandResult {
+        let player = self.world.player().ok_or(())?;
+        let spell = self.world.known_spell(player, slot).ok_or(())?;
+
+        if spell.is_directed() {
+            self.enter_state(State::Aim(AimAction::Cast(slot)));
+            Ok(Vec::new())
+        } else {
+            self.world.cast(slot)
+        }
+    }
+
+    fn aim(&mut self, action: AimAction, dir: Dir6) -> CommandResult {
+        let ret = match action {
+            AimAction::Zap(slot) => self.world.zap_item(slot, dir),
+            AimAction::Throw(slot) => self.world.throw(slot, dir),
+            AimAction::Cast(slot) => self.world.cast_directed(slot, dir),
+            AimAction::CloseDoor => self.world.close_door(dir),
+        };
+        // Go back to main state whether the action succeeded or not, there's no point in
+        // retrying a direction that already failed.
         self.enter_state(State::Main);
-        Ok(ret)
+        ret
     }
 
-    fn aim_input(&mut self, slot: Slot, scancode: Scancode) -> CommandResult {
+    fn aim_input(&mut self, action: AimAction, scancode: Scancode) -> CommandResult {
         use scancode::Scancode::*;
         match scancode {
-            Q => self.zap(slot, Dir6::Northwest),
-            W => self.zap(slot, Dir6::North),
-            E => self.zap(slot, Dir6::Northeast),
-            A => self.zap(slot, Dir6::Southwest),
-            S => self.zap(slot, Dir6::South),
-            D => self.zap(slot, Dir6::Southeast),
+            Q => self.aim(action, Dir6::Northwest),
+            W => self.aim(action, Dir6::North),
+            E => self.aim(action, Dir6::Northeast),
+            A => self.aim(action, Dir6::Southwest),
+            S => self.aim(action, Dir6::South),
+            D => self.aim(action, Dir6::Southeast),
             Escape => {
                 self.enter_state(State::Main);
                 Ok(Vec::new())
@@ -153,6 +226,28 @@ impl GameLoop {
         }
     }
 
+    fn container_input(&mut self, scancode: Scancode) -> CommandResult {
+        use scancode::Scancode::*;
+        let player = self.world.player().ok_or(())?;
+        let container = self.world.reachable_container(player).ok_or(())?;
+        let contents = self.world.container_contents(container);
+
+        for (slot, &item) in container_keys().iter().zip(contents.iter()) {
+            if scancode == slot.code {
+                // Stay in the container view so that several items can be taken out.
+                return self.world.take_out(item);
+            }
+        }
+
+        match scancode {
+            Escape => {
+                self.enter_state(State::Main);
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
     fn enter_state(&mut self, new_state: State) {
         if self.state == new_state {
             return;
@@ -182,7 +277,9 @@ impl GameLoop {
 
                 if let Some(item) = self.world.entity_equipped(player, slot) {
                     match self.world.item_type(item) {
-                        Some(ItemType::UntargetedUsable(_)) => {
+                        Some(ItemType::UntargetedUsable(_)) |
+                        Some(ItemType::Potion(_)) |
+                        Some(ItemType::Spell(_)) => {
                             let ret = self.world.use_item(slot)?;
                             self.enter_state(State::Main);
                             return Ok(ret);
@@ -197,6 +294,21 @@ impl GameLoop {
                 }
                 Err(())
             }
+            InventoryAction::Throw => {
+                let player = self.world.player().ok_or(())?;
+                if self.world.entity_equipped(player, slot).is_none() {
+                    return Err(());
+                }
+                self.enter_state(State::Aim(AimAction::Throw(slot)));
+                Ok(Vec::new())
+            }
+            InventoryAction::Store => {
+                let ret = self.world.store(slot);
+                if ret.is_ok() {
+                    self.enter_state(State::Main);
+                }
+                ret
+            }
         }
     }
 
@@ -250,8 +362,13 @@ impl GameLoop {
                 &format!("{})", slot.key),
             );
             slot_name_pos = c.draw_text(slot_name_pos, Align::Left, text_color, slot.name);
-            let item_name = if let Some(item) = self.world.entity_equipped(player, slot.slot) {
-                self.world.entity_name(item)
+            let item_name = if let Some(spell) = self.world.known_spell(player, slot.slot) {
+                format!("{} ({} mana)", spell.name(), spell.mana_cost())
+            } else if let Some(item) = self.world.entity_equipped(player, slot.slot) {
+                match self.world.item_count(item) {
+                    n if n > 1 => format!("{} x{}", self.world.entity_name(item), n),
+                    _ => self.world.entity_name(item),
+                }
             } else {
                 "".to_string()
             };
@@ -262,21 +379,61 @@ impl GameLoop {
         Ok(())
     }
 
-    pub fn draw(&mut self, context: &mut display::Backend, screen_area: &Rect<f32>) {
-        // Ugh
-        self.world.player().map(|x| {
-            self.world.location(x).map(|l| self.camera_loc = l)
-        });
+    fn draw_container(&mut self, c: &mut display::Backend) -> Result<(), ()> {
+        let player = self.world.player().ok_or(())?;
+        let container = self.world.reachable_container(player).ok_or(())?;
 
-        let mut view = display::WorldView::new(self.camera_loc, *screen_area);
-        view.show_cursor = true;
+        c.fill_rect(
+            FracRect::new(FracPoint2D::new(0.0, 0.0), FracSize2D::new(1.0, 1.0)),
+            [0.0, 0.0, 0.0, 0.99],
+        );
 
-        view.draw(&self.world, context);
+        let text_color = [1.0, 1.0, 1.0, 1.0];
+        let mut letter_pos = c.draw_text(
+            Point2D::new(0.0, 0.0),
+            Align::Left,
+            text_color,
+            &format!("Inside the {}:", self.world.entity_name(container)),
+        );
+        let mut item_name_pos = Point2D::new(20.0, letter_pos.y);
+
+        let contents = self.world.container_contents(container);
+        if contents.is_empty() {
+            c.draw_text(item_name_pos, Align::Left, text_color, "(empty)");
+        }
+
+        for (slot, &item) in container_keys().iter().zip(contents.iter()) {
+            letter_pos = c.draw_text(
+                letter_pos,
+                Align::Left,
+                text_color,
+                &format!("{})", slot.key),
+            );
+            let item_name = match self.world.item_count(item) {
+                n if n > 1 => format!("{} x{}", self.world.entity_name(item), n),
+                _ => self.world.entity_name(item),
+            };
+            item_name_pos = c.draw_text(item_name_pos, Align::Left, text_color, &item_name);
+        }
+
+        Ok(())
+    }
+
+    pub fn draw(&mut self, context: &mut display::Backend, screen_area: &Rect<f32>) {
+        if let Some(loc) = self.world.player().and_then(|x| self.world.location(x)) {
+            self.view.set_camera(loc);
+        }
+        self.view.set_screen_area(*screen_area);
+
+        self.view.draw(&self.world, context);
 
         match self.state {
             State::Inventory(_) => {
                 let _ = self.draw_inventory(context);
             }
+            State::Container => {
+                let _ = self.draw_container(context);
+            }
             State::Console => {
                 let mut console_area = *screen_area;
                 console_area.size.height /= 2.0;
@@ -292,8 +449,9 @@ impl GameLoop {
         if let Some(scancode) = context.poll_key().and_then(|k| Scancode::new(k.scancode)) {
             let ret = match self.state {
                 State::Inventory(_) => self.inventory_input(scancode),
+                State::Container => self.container_input(scancode),
                 State::Console => self.console_input(scancode),
-                State::Aim(AimAction::Zap(slot)) => self.aim_input(slot, scancode),
+                State::Aim(action) => self.aim_input(action, scancode),
                 _ => self.game_input(scancode),
             };
 
@@ -321,14 +479,14 @@ struct SlotData {
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 static SLOT_DATA: [SlotData; 34] = [
-    SlotData { key: '1', code: Scancode::Num1, slot: Slot::Spell1,     name: "Ability" },
-    SlotData { key: '2', code: Scancode::Num2, slot: Slot::Spell2,     name: "Ability" },
-    SlotData { key: '3', code: Scancode::Num3, slot: Slot::Spell3,     name: "Ability" },
-    SlotData { key: '4', code: Scancode::Num4, slot: Slot::Spell4,     name: "Ability" },
-    SlotData { key: '5', code: Scancode::Num5, slot: Slot::Spell5,     name: "Ability" },
-    SlotData { key: '6', code: Scancode::Num6, slot: Slot::Spell6,     name: "Ability" },
-    SlotData { key: '7', code: Scancode::Num7, slot: Slot::Spell7,     name: "Ability" },
-    SlotData { key: '8', code: Scancode::Num8, slot: Slot::Spell8,     name: "Ability" },
+    SlotData { key: '1', code: Scancode::Num1, slot: Slot::Spell1,     name: "Spell" },
+    SlotData { key: '2', code: Scancode::Num2, slot: Slot::Spell2,     name: "Spell" },
+    SlotData { key: '3', code: Scancode::Num3, slot: Slot::Spell3,     name: "Spell" },
+    SlotData { key: '4', code: Scancode::Num4, slot: Slot::Spell4,     name: "Spell" },
+    SlotData { key: '5', code: Scancode::Num5, slot: Slot::Spell5,     name: "Spell" },
+    SlotData { key: '6', code: Scancode::Num6, slot: Slot::Spell6,     name: "Spell" },
+    SlotData { key: '7', code: Scancode::Num7, slot: Slot::Spell7,     name: "Spell" },
+    SlotData { key: '8', code: Scancode::Num8, slot: Slot::Spell8,     name: "Spell" },
     SlotData { key: 'a', code: Scancode::A,    slot: Slot::Melee,      name: "Weapon" },
     SlotData { key: 'b', code: Scancode::B,    slot: Slot::Ranged,     name: "Ranged" },
     SlotData { key: 'c', code: Scancode::C,    slot: Slot::Head,       name: "Head" },
@@ -356,3 +514,6 @@ static SLOT_DATA: [SlotData; 34] = [
     SlotData { key: 'y', code: Scancode::Y,    slot: Slot::InventoryY, name: "" },
     SlotData { key: 'z', code: Scancode::Z,    slot: Slot::InventoryZ, name: "" },
 ];
+
+/// Letter keys for picking items out of containers.
+fn container_keys() -> &'static [SlotData] { &SLOT_DATA[8..] }
@@ -0,0 +1,46 @@
+#![cfg(feature = "nightly")]
+#![cfg_attr(feature = "nightly", feature(test))]
+
+// Benchmarks need the unstable test crate, run with `cargo +nightly bench --features nightly`.
+//
+// `bench_fov_moving` misses the FOV cache every time, so it shows the cost of computing FOVs
+// without caching. Compare `bench_fov_stationary` to it to see what the cache gains.
+
+extern crate test;
+extern crate world;
+
+use test::Bencher;
+use world::{Location, Query, World};
+
+const RANGE: u32 = 12;
+
+fn overworld() -> (World, Location) {
+    let w = World::new(1);
+    let origin = w.location(w.player().unwrap()).unwrap();
+    assert_eq!(origin.z, 0, "Player should start on the open overworld");
+    (w, origin)
+}
+
+/// FOV from a new origin every time, so every FOV has to be computed from scratch.
+#[bench]
+fn bench_fov_moving(b: &mut Bencher) {
+    let (w, origin) = overworld();
+    // More origins than the cache holds.
+    let origins: Vec<Location> = (0..1024)
+        .map(|i| Location::new(origin.x + (i % 32) as i16, origin.y + (i / 32) as i16, 0))
+        .collect();
+    let mut i = 0;
+
+    b.iter(|| {
+        i = (i + 1) % origins.len();
+        w.fov_from(origins[i], RANGE)
+    });
+}
+
+/// Repeated FOV from an unchanged origin, like a player standing still for several turns.
+#[bench]
+fn bench_fov_stationary(b: &mut Bencher) {
+    let (w, origin) = overworld();
+
+    b.iter(|| w.fov_from(origin, RANGE));
+}
@@ -0,0 +1,165 @@
+use item::ItemType;
+use rand::Rng;
+use stats::Stats;
+
+/// Randomly rolled modifier that makes an item better than the standard one.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Affix {
+    /// Plain "+n" bonus to attack for weapons or armor for worn gear.
+    Enchant(i32),
+    Keen,
+    Heavy,
+    Sturdy,
+    Glowing,
+    OfWarding,
+    OfSight,
+    OfShadows,
+    OfMight,
+}
+
+use self::Affix::*;
+
+/// Prefixes and suffixes that can be rolled for items.
+static AFFIXES: [Affix; 8] = [
+    Keen,
+    Heavy,
+    Sturdy,
+    Glowing,
+    OfWarding,
+    OfSight,
+    OfShadows,
+    OfMight,
+];
+
+fn is_weapon(item_type: ItemType) -> bool {
+    match item_type {
+        ItemType::MeleeWeapon | ItemType::RangedWeapon => true,
+        _ => false,
+    }
+}
+
+fn is_worn(item_type: ItemType) -> bool {
+    match item_type {
+        ItemType::Helmet | ItemType::Armor | ItemType::Boots => true,
+        _ => false,
+    }
+}
+
+impl Affix {
+    pub fn name(self) -> String {
+        match self {
+            Enchant(n) => format!("{:+}", n),
+            Keen => "keen".to_string(),
+            Heavy => "heavy".to_string(),
+            Sturdy => "sturdy".to_string(),
+            Glowing => "glowing".to_string(),
+            OfWarding => "of warding".to_string(),
+            OfSight => "of sight".to_string(),
+            OfShadows => "of shadows".to_string(),
+            OfMight => "of might".to_string(),
+        }
+    }
+
+    pub fn is_suffix(self) -> bool {
+        match self {
+            OfWarding | OfSight | OfShadows | OfMight => true,
+            _ => false,
+        }
+    }
+
+    /// Shallowest dungeon depth the affix can show up at.
+    fn min_depth(self) -> i32 {
+        match self {
+            Glowing => 1,
+            OfShadows | Heavy => 2,
+            OfMight => 3,
+            _ => 0,
+        }
+    }
+
+    fn applies_to(self, item_type: ItemType) -> bool {
+        match self {
+            Enchant(_) | Glowing | OfWarding | OfSight | OfShadows => {
+                is_weapon(item_type) || is_worn(item_type)
+            }
+            Keen | Heavy | OfMight => is_weapon(item_type),
+            Sturdy => is_worn(item_type),
+        }
+    }
+
+    /// Stat modifier the affix gives to an item of the given type.
+    pub fn stats(self, item_type: ItemType) -> Stats {
+        let zero = Stats::default();
+        match self {
+            Enchant(n) if is_weapon(item_type) => zero.attack(n),
+            Enchant(n) => zero.armor(n),
+            Keen => zero.attack(2),
+            Heavy => Stats { power: 2, ..zero },
+            Sturdy => zero.armor(1).defense(1),
+            Glowing => zero.light(3),
+            OfWarding => zero.defense(2),
+            OfSight => zero.perception(2),
+            OfShadows => zero.stealth(2),
+            OfMight => Stats { power: 3, ..zero },
+        }
+    }
+}
+
+/// Roll random affixes for a new item, deeper levels give more and better affixes.
+pub fn roll<R: Rng>(rng: &mut R, item_type: ItemType, depth: i32) -> Vec<Affix> {
+    const BASE_CHANCE: f32 = 0.2;
+    const CHANCE_PER_DEPTH: f32 = 0.05;
+
+    let mut ret = Vec::new();
+    let chance = BASE_CHANCE + CHANCE_PER_DEPTH * depth as f32;
+
+    if Enchant(1).applies_to(item_type) && rng.gen::<f32>() < chance {
+        ret.push(Enchant(rng.gen_range(1, 2 + depth / 2)));
+    }
+
+    for &is_suffix in &[false, true] {
+        let options: Vec<Affix> = AFFIXES
+            .iter()
+            .cloned()
+            .filter(|a| {
+                a.is_suffix() == is_suffix && a.min_depth() <= depth && a.applies_to(item_type)
+            })
+            .collect();
+
+        if !options.is_empty() && rng.gen::<f32>() < chance {
+            ret.push(options[rng.gen_range(0, options.len())]);
+        }
+    }
+
+    ret
+}
+
+/// Return the total stat modifier from affixes.
+pub fn stats(affixes: &[Affix], item_type: ItemType) -> Stats {
+    affixes.iter().fold(Stats::default(), |acc, a| acc + a.stats(item_type))
+}
+
+/// Decorate an item name with its affixes.
+pub fn item_name(base: &str, affixes: &[Affix]) -> String {
+    let mut words: Vec<String> = affixes
+        .iter()
+        .filter(|a| !a.is_suffix())
+        .map(|a| a.name())
+        .collect();
+    words.push(base.to_string());
+    words.extend(affixes.iter().filter(|a| a.is_suffix()).map(|a| a.name()));
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::Affix::*;
+    use super::item_name;
+
+    #[test]
+    fn test_item_name() {
+        assert_eq!(item_name("sword", &[]), "sword");
+        assert_eq!(item_name("sword", &[Enchant(2), Keen, OfWarding]), "+2 keen sword of warding");
+        assert_eq!(item_name("helmet", &[OfSight, Sturdy]), "sturdy helmet of sight");
+    }
+}
@@ -0,0 +1,142 @@
+use calx_grid::Dir6;
+use effect::{Damage, Effect};
+use field::Field;
+use location::Location;
+use std::collections::BTreeSet;
+
+/// Type of a gaseous cell effect.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+pub enum CloudKind {
+    /// Flames that burn whatever they are on and give off smoke.
+    Fire,
+    /// Thick smoke that can't be seen through.
+    Smoke,
+    /// Poisonous gas.
+    Gas,
+}
+
+/// Time-varying effect occupying a map cell.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Cloud {
+    pub kind: CloudKind,
+    /// How much of the cloud there is, the cloud disappears when this goes to zero.
+    pub density: u8,
+}
+
+/// How much density a cloud loses when spreading to an adjacent cell.
+const SPREAD_LOSS: u8 = 3;
+
+impl Cloud {
+    pub fn new(kind: CloudKind, density: u8) -> Cloud { Cloud { kind, density } }
+
+    pub fn blocks_sight(&self) -> bool { self.kind == CloudKind::Smoke }
+
+    /// Effect applied to whatever is in the cloud each turn.
+    pub fn effect(&self) -> Option<Effect> {
+        match self.kind {
+            CloudKind::Fire => Some(Effect::Hit {
+                amount: 2,
+                damage: Damage::Fire,
+            }),
+            CloudKind::Gas => Some(Effect::Hit {
+                amount: 1,
+                damage: Damage::Poison,
+            }),
+            CloudKind::Smoke => None,
+        }
+    }
+
+    /// Return the cloud with density reduced by amount, or `None` if nothing is left.
+    fn thinned(self, amount: u8) -> Option<Cloud> {
+        if self.density > amount {
+            Some(Cloud::new(self.kind, self.density - amount))
+        } else {
+            None
+        }
+    }
+}
+
+/// Advance a cloud field by one turn.
+///
+/// Smoke and gas diffuse into cells where `is_open` is true, fire spreads into cells where
+/// `is_fuel` is true and gives off smoke around it. Every cloud thins out over time, so the
+/// field eventually empties.
+pub fn step<F, G>(clouds: &Field<Option<Cloud>>, is_open: F, is_fuel: G) -> Field<Option<Cloud>>
+where
+    F: Fn(Location) -> bool,
+    G: Fn(Location) -> bool,
+{
+    let mut candidates = BTreeSet::new();
+    for (&loc, _) in clouds.iter() {
+        candidates.insert(loc);
+        for &d in Dir6::iter() {
+            candidates.insert(loc + d);
+        }
+    }
+
+    let mut ret = Field::new();
+    for loc in candidates {
+        // The densest incoming cloud wins the cell.
+        let mut best = clouds.get(loc).and_then(|c| c.thinned(1));
+
+        for &d in Dir6::iter() {
+            let neighbor = match clouds.get(loc + d) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let incoming = match neighbor.kind {
+                CloudKind::Fire if is_fuel(loc) => neighbor.thinned(SPREAD_LOSS),
+                CloudKind::Fire if is_open(loc) => {
+                    Cloud::new(CloudKind::Smoke, neighbor.density).thinned(SPREAD_LOSS)
+                }
+                CloudKind::Fire => None,
+                _ if is_open(loc) => neighbor.thinned(SPREAD_LOSS),
+                _ => None,
+            };
+
+            if let Some(c) = incoming {
+                if best.map_or(true, |b| c.density > b.density) {
+                    best = Some(c);
+                }
+            }
+        }
+
+        ret.set(loc, best);
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::{step, Cloud, CloudKind};
+    use field::Field;
+    use location::Location;
+
+    #[test]
+    fn test_cloud_dissipates() {
+        let origin = Location::new(0, 0, 0);
+        let mut clouds = Field::new();
+        clouds.set(origin, Some(Cloud::new(CloudKind::Gas, 10)));
+
+        clouds = step(&clouds, |_| true, |_| false);
+        assert!(clouds.iter().count() > 1, "Gas did not spread");
+
+        for _ in 0..10 {
+            clouds = step(&clouds, |_| true, |_| false);
+        }
+        assert_eq!(clouds.iter().count(), 0, "Gas did not dissipate");
+    }
+
+    #[test]
+    fn test_cloud_blocked() {
+        let origin = Location::new(0, 0, 0);
+        let mut clouds = Field::new();
+        clouds.set(origin, Some(Cloud::new(CloudKind::Smoke, 10)));
+
+        clouds = step(&clouds, |loc| loc == origin, |_| false);
+        assert_eq!(clouds.iter().count(), 1);
+        assert_eq!(clouds.get(origin), Some(Cloud::new(CloudKind::Smoke, 9)));
+    }
+}
@@ -1,6 +1,7 @@
+use calx_ecs::Entity;
 use calx_grid::Dir6;
 use event::Event;
-use item::Slot;
+use item::{MagicEffect, Slot};
 use mutate::Mutate;
 
 pub type CommandResult = Result<Vec<Event>, ()>;
@@ -29,6 +30,24 @@ pub trait Command: Mutate + Sized {
         self.next_tick()
     }
 
+    /// The player closes an open door in a direction.
+    ///
+    /// Will fail if there is no open door or if something is blocking the doorway.
+    fn close_door(&mut self, dir: Dir6) -> CommandResult {
+        let player = self.player().ok_or(())?;
+        self.entity_close_door(player, dir)?;
+        self.next_tick()
+    }
+
+    /// The player spends a turn searching the surroundings for hidden things.
+    fn search(&mut self) -> CommandResult {
+        const SEARCH_ONE_IN: u32 = 2;
+
+        let player = self.player().ok_or(())?;
+        self.detect_hidden(player, SEARCH_ONE_IN);
+        self.next_tick()
+    }
+
     /// Pass a turn without action from the player.
     ///
     /// Will usually succeed, but some games might not let the player pass turns.
@@ -41,7 +60,10 @@ pub trait Command: Mutate + Sized {
     fn take(&mut self) -> CommandResult {
         let player = self.player().ok_or(())?;
         let location = self.location(player).ok_or(())?;
-        if let Some(item) = self.item_at(location) {
+        let item = self.entities_at(location).into_iter().find(|&e| {
+            self.is_item(e) && self.is_portable(e)
+        });
+        if let Some(item) = item {
             self.entity_take(player, item)?;
             self.next_tick()
         } else {
@@ -49,18 +71,65 @@ pub trait Command: Mutate + Sized {
         }
     }
 
+    /// Put the item held in slot into the container the player can reach.
+    fn store(&mut self, slot: Slot) -> CommandResult {
+        let player = self.player().ok_or(())?;
+        let container = self.reachable_container(player).ok_or(())?;
+        self.entity_store(player, slot, container)?;
+        self.next_tick()
+    }
+
+    /// Take an item out of the container the player can reach.
+    fn take_out(&mut self, item: Entity) -> CommandResult {
+        let player = self.player().ok_or(())?;
+        let container = self.reachable_container(player).ok_or(())?;
+        if !self.container_contents(container).contains(&item) {
+            return Err(());
+        }
+        self.entity_take(player, item)?;
+        self.next_tick()
+    }
+
     /// Drop item held in slot.
+    ///
+    /// Only one item is dropped from a stack.
     fn drop(&mut self, slot: Slot) -> CommandResult {
         let player = self.player().ok_or(())?;
         let location = self.location(player).ok_or(())?;
         if let Some(item) = self.entity_equipped(player, slot) {
-            self.place_entity(item, location);
+            if self.is_stuck(player, slot) {
+                self.reveal_curse(item);
+                msg!(self, "You can't remove the {}.", self.entity_name(item));
+                return Err(());
+            }
+            if self.split_stack(item, location).is_none() {
+                self.place_entity(item, location);
+            }
             self.next_tick()
         } else {
             Err(())
         }
     }
 
+    /// Throw the item held in slot in a direction.
+    ///
+    /// Only one item is thrown from a stack.
+    fn throw(&mut self, slot: Slot, dir: Dir6) -> CommandResult {
+        let player = self.player().ok_or(())?;
+        let location = self.location(player).ok_or(())?;
+        let stack = self.entity_equipped(player, slot).ok_or(())?;
+        if self.is_stuck(player, slot) {
+            self.reveal_curse(stack);
+            msg!(self, "You can't remove the {}.", self.entity_name(stack));
+            return Err(());
+        }
+
+        let item = self.unstack(stack).unwrap_or(stack);
+        self.throw_item(item, location, dir, Some(player));
+        self.regenerate_stats(player);
+        self.next_tick()
+    }
+
     /// Swap item between equipment and inventory slots
     ///
     /// Behavior depends on slot. Equipment slots go to inventory, inventory slots go to equip. The
@@ -71,7 +140,11 @@ pub trait Command: Mutate + Sized {
 
         let swap_slot = if slot.is_equipment_slot() {
             // Remove equipped.
-            // TODO: Items that can't be removed because of curses etc. trip here.
+            if self.is_stuck(player, slot) {
+                self.reveal_curse(item);
+                msg!(self, "You can't remove the {}.", self.entity_name(item));
+                return Err(());
+            }
             self.free_bag_slot(player).ok_or(())?
         } else {
             // Equip from bag.
@@ -80,17 +153,36 @@ pub trait Command: Mutate + Sized {
         };
 
         self.equip_item(item, player, swap_slot);
+        if self.is_stuck(player, swap_slot) {
+            msg!(self, "The {} is cursed!", self.entity_name(item));
+            self.reveal_curse(item);
+        }
         self.regenerate_stats(player);
         self.next_tick()
     }
 
     /// Use a nontargeted effect item.
+    ///
+    /// Reading a tome teaches its spell and uses up the tome.
     fn use_item(&mut self, slot: Slot) -> CommandResult {
         let player = self.player().ok_or(())?;
         let location = self.location(player).ok_or(())?;
         let item = self.entity_equipped(player, slot).ok_or(())?;
         if self.uses_left(item) > 0 {
-            self.cast_spell(location, item, Some(player))?;
+            if let Some(effect) = self.potion_effect(item) {
+                msg!(self, "You drink the {}.", self.entity_name(item));
+                self.apply_effect_to_entity(&effect, player, None);
+            } else if let Some(spell) = self.spell_effect(item) {
+                if self.learn_spell(player, spell).is_err() {
+                    msg!(self, "You can't learn anything from the {}.", self.entity_name(item));
+                    return Err(());
+                }
+                msg!(self, "You learn the spell of {}.", spell.name());
+            } else {
+                let effect = self.undirected_effect(item).ok_or(())?;
+                self.cast_spell(location, effect, Some(item), Some(player));
+            }
+            self.identify_item(item);
             self.drain_charge(item);
         } else {
             msg!(self, "Nothing happens.");
@@ -103,7 +195,45 @@ pub trait Command: Mutate + Sized {
         let player = self.player().ok_or(())?;
         let location = self.location(player).ok_or(())?;
         let item = self.entity_equipped(player, slot).ok_or(())?;
-        self.cast_directed_spell(location, dir, item, Some(player))?;
+        let effect = self.directed_effect(item).ok_or(())?;
+        self.cast_directed_spell(location, dir, effect, Some(player));
+        self.identify_item(item);
         self.next_tick()
     }
+
+    /// Cast an undirected spell the player has learned in a spell slot.
+    fn cast(&mut self, slot: Slot) -> CommandResult {
+        let player = self.player().ok_or(())?;
+        let location = self.location(player).ok_or(())?;
+        let effect = match self.known_spell(player, slot) {
+            Some(effect) if !effect.is_directed() => effect,
+            _ => return Err(()),
+        };
+        // Paying is the last thing that can fail, so mana is never spent on a failed cast.
+        self.pay_for_spell(player, effect)?;
+        self.cast_spell(location, effect, None, Some(player));
+        self.next_tick()
+    }
+
+    /// Cast a directed spell the player has learned in a spell slot.
+    fn cast_directed(&mut self, slot: Slot, dir: Dir6) -> CommandResult {
+        let player = self.player().ok_or(())?;
+        let location = self.location(player).ok_or(())?;
+        let effect = match self.known_spell(player, slot) {
+            Some(effect) if effect.is_directed() => effect,
+            _ => return Err(()),
+        };
+        self.pay_for_spell(player, effect)?;
+        self.cast_directed_spell(location, dir, effect, Some(player));
+        self.next_tick()
+    }
+
+    /// Spend the mana a spell needs, complain if the caster is out of mana.
+    fn pay_for_spell(&mut self, caster: Entity, effect: MagicEffect) -> Result<(), ()> {
+        if self.spend_mana(caster, effect.mana_cost()).is_err() {
+            msg!(self, "Not enough mana for {}.", effect.name());
+            return Err(());
+        }
+        Ok(())
+    }
 }
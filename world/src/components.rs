@@ -1,10 +1,11 @@
 use FovStatus;
+use affix::Affix;
 use calx_ecs::Entity;
-use item::ItemType;
+use item::{ItemType, MagicEffect};
 use location::Location;
 use location_set::LocationSet;
 use stats::Stats;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// The visual representation for an entity
 ///
@@ -15,11 +16,28 @@ pub enum Icon {
     Snake,
     Dreg,
     Ogre,
+    Wraith,
+    Salamander,
+    Slime,
 
     Sword,
+    Torch,
     Wand1,
     Wand2,
+    Wand3,
+    Wand4,
     Scroll1,
+    Tome,
+    Potion1,
+    Potion2,
+    Key,
+    Trap,
+    Helmet,
+    Armor,
+    HealthOrb,
+    Bag,
+    Chest,
+    Corpse,
 }
 
 /// Entity name and appearance.
@@ -48,6 +66,17 @@ impl Desc {
 pub struct MapMemory {
     pub seen: LocationSet,
     pub remembered: LocationSet,
+    /// Locations of traps that have been found.
+    pub traps: LocationSet,
+    /// What entities looked like when their locations were last seen.
+    pub entities: BTreeMap<Location, Vec<Appearance>>,
+}
+
+/// Remembered look of an entity.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Appearance {
+    pub icon: Icon,
+    pub is_mob: bool,
 }
 
 impl MapMemory {
@@ -63,11 +92,31 @@ impl MapMemory {
 }
 
 
+/// Hidden hazard that goes off when a mob steps on it.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Trap {
+    pub kind: TrapKind,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TrapKind {
+    /// Hurts whoever falls in.
+    Pit,
+    /// Shoots a poisoned dart.
+    Dart,
+    /// Makes a racket that wakes up nearby monsters.
+    Alarm,
+    /// Moves the victim to a random spot on the level.
+    Teleport,
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Brain {
     pub state: BrainState,
     pub alignment: Alignment,
     pub shout: ShoutType,
+    /// Does the mob use up instant items it steps on.
+    pub scavenger: bool,
 }
 
 impl Brain {
@@ -79,6 +128,7 @@ impl Brain {
         Brain {
             state: BrainState::PlayerControl,
             alignment: Alignment::Good,
+            scavenger: true,
             ..Default::default()
         }
     }
@@ -90,6 +140,7 @@ impl Default for Brain {
             state: BrainState::Asleep,
             alignment: Alignment::Evil,
             shout: ShoutType::Shout,
+            scavenger: false,
         }
     }
 }
@@ -145,6 +196,8 @@ pub struct Health {
     pub wounds: i32,
     /// Armor points get eaten away before you start getting wounds.
     pub armor: i32,
+    /// Mana spent on spells, regenerates over time.
+    pub spent_mana: i32,
 }
 
 impl Health {
@@ -152,12 +205,27 @@ impl Health {
 }
 
 
+/// Spells a mob has learned, in the order of the spell slots they are cast from.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Spellbook {
+    pub spells: Vec<MagicEffect>,
+}
+
+
 /// Items can be picked up and carried and they do stuff.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Item {
     pub item_type: ItemType,
     /// How many uses a wand or similar has left.
     pub charges: u32,
+    /// Number of identical items in a stack.
+    pub count: u32,
+    /// Cursed items can't be removed once equipped.
+    pub cursed: bool,
+    /// Whether the player has found out about the curse.
+    pub curse_known: bool,
+    /// Randomly rolled modifiers, their stats are included in the item's stats.
+    pub affixes: Vec<Affix>,
 }
 
 
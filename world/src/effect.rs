@@ -19,4 +19,5 @@ pub enum Damage {
     Fire,
     Electricity,
     Cold,
+    Poison,
 }
@@ -1,5 +1,7 @@
+use affix;
 use calx_alg::WeightedChoice;
-use components::{Icon, Brain, Desc, Health, Item, MapMemory, ShoutType, StatsComponent, Statuses};
+use components::{Icon, Brain, Desc, Health, Item, MapMemory, ShoutType, Spellbook, StatsComponent,
+                 Statuses, Trap, TrapKind};
 use item::ItemType;
 use rand::Rng;
 use stats::{Intrinsic, Stats};
@@ -17,6 +19,8 @@ pub struct Form {
     ///
     /// More powerful entities only start showing up in large depths.
     pub min_depth: i32,
+    /// Base probability for a spawned item to be cursed.
+    pub curse_chance: f32,
     //    /// Type of thing.
     //    pub category: FormType,
     /// Actual components to set up the thing.
@@ -48,6 +52,7 @@ impl Form {
         Form {
             rarity: 1.0,
             min_depth: 0,
+            curse_chance: 0.0,
             // category: FormType::Creature,
             loadout: Loadout::new()
                 .c(StatsComponent::new(Stats::new(power, intrinsics)))
@@ -62,16 +67,30 @@ impl Form {
         Form {
             rarity: 1.0,
             min_depth: 0,
+            curse_chance: 0.0,
             loadout: Loadout::new()
                 .c(StatsComponent::new(Stats::new(power, &[])))
                 .c(Desc::new(name, brush))
                 .c(Item {
                     item_type,
                     charges: 1,
+                    count: 1,
+                    cursed: false,
+                    curse_known: false,
+                    affixes: Vec::new(),
                 }),
         }
     }
 
+    pub fn trap(name: &str, kind: TrapKind) -> Form {
+        Form {
+            rarity: 1.0,
+            min_depth: 0,
+            curse_chance: 0.0,
+            loadout: Loadout::new().c(Desc::new(name, Icon::Trap)).c(Trap { kind }),
+        }
+    }
+
     pub fn rarity(mut self, rarity: f32) -> Form {
         self.rarity = rarity;
         self
@@ -82,11 +101,48 @@ impl Form {
         self
     }
 
+    fn map_stats<F: Fn(Stats) -> Stats>(mut self, f: F) -> Form {
+        let mut stats = self.loadout.stats.expect("Must have stats");
+        stats.base = f(stats.base);
+        stats.actual = f(stats.actual);
+        self.loadout.stats = Some(stats);
+        self
+    }
+
+    /// Make the entity give off light with the given radius.
+    pub fn light(self, radius: i32) -> Form { self.map_stats(|s| s.light(radius)) }
+
+    pub fn perception(self, perception: i32) -> Form {
+        self.map_stats(|s| s.perception(perception))
+    }
+
+    pub fn stealth(self, stealth: i32) -> Form { self.map_stats(|s| s.stealth(stealth)) }
+
+    pub fn armor(self, armor: i32) -> Form { self.map_stats(|s| s.armor(armor)) }
+
+    pub fn mana(self, mana: i32) -> Form { self.map_stats(|s| s.mana(mana)) }
+
+    /// Make spawned items cursed with the given probability at depth zero.
+    ///
+    /// Curses get more common deeper down.
+    pub fn curse_chance(mut self, chance: f32) -> Form {
+        self.curse_chance = chance;
+        self
+    }
+
     pub fn player(mut self) -> Form {
         self.loadout.brain = Some(Brain::player());
         self
     }
 
+    /// Make the mob use up instant items it steps on.
+    pub fn scavenger(mut self) -> Form {
+        let mut brain = self.loadout.brain.expect("Must be mob");
+        brain.scavenger = true;
+        self.loadout.brain = Some(brain);
+        self
+    }
+
     pub fn reptile(mut self) -> Form {
         let mut brain = self.loadout.brain.expect("Must be mob");
         brain.shout = ShoutType::Hiss;
@@ -107,8 +163,40 @@ impl Form {
 
     pub fn is_mob(&self) -> bool { self.loadout.brain.is_some() }
 
+    pub fn is_trap(&self) -> bool { self.loadout.trap.is_some() }
+
     pub fn at_depth(&self, depth: i32) -> bool { self.min_depth <= depth }
 
+    /// Return the loadout for spawning the form at depth, rolling for affixes and a curse on
+    /// items.
+    pub fn roll_loadout<R: Rng>(&self, rng: &mut R, depth: i32) -> Loadout {
+        const CURSE_CHANCE_PER_DEPTH: f32 = 0.05;
+
+        let mut loadout = self.loadout.clone();
+
+        if let Some(ref mut item) = loadout.item {
+            item.affixes = affix::roll(rng, item.item_type, depth);
+            if let Some(ref mut stats) = loadout.stats {
+                let bonus = affix::stats(&item.affixes, item.item_type);
+                stats.base = stats.base + bonus;
+                stats.actual = stats.actual + bonus;
+            }
+        }
+
+        if self.curse_chance > 0.0 &&
+            rng.gen::<f32>() < self.curse_chance + CURSE_CHANCE_PER_DEPTH * depth as f32
+        {
+            if let Some(ref mut item) = loadout.item {
+                item.cursed = true;
+            }
+            if let Some(ref mut stats) = loadout.stats {
+                stats.base = stats.base.cursed();
+                stats.actual = stats.actual.cursed();
+            }
+        }
+        loadout
+    }
+
     pub fn c<C: Component>(mut self, comp: C) -> Form {
         self.loadout = self.loadout.c(comp);
         self
@@ -119,16 +207,43 @@ lazy_static! {
     pub static ref FORMS: Vec<Form> = {
         use item::MagicEffect::*;
         vec![
-        Form::mob("player",     Icon::Player,     10, &[Hands]).rarity(0.0).player()
-            .c(MapMemory::default()),
-        Form::mob("dreg",       Icon::Dreg,       2,  &[Hands]),
-        Form::mob("snake",      Icon::Snake,      1,  &[]).reptile(),
-        Form::mob("ogre",       Icon::Ogre,       5,  &[Hands]).depth(2),
-
-        Form::item("sword",     Icon::Sword,     10,  ItemType::MeleeWeapon).rarity(10.0),
+        Form::mob("player",     Icon::Player,     10, &[Hands, Swimming]).rarity(0.0).player()
+            .light(2).stealth(2).mana(10).c(MapMemory::default()).c(Spellbook::default()),
+        Form::mob("dreg",       Icon::Dreg,       2,  &[Hands]).scavenger(),
+        Form::mob("snake",      Icon::Snake,      1,  &[Swimming]).reptile().perception(2),
+        Form::mob("ogre",       Icon::Ogre,       5,  &[Hands]).depth(2).perception(-4),
+        Form::mob("glow slime", Icon::Slime,      3,  &[]).light(3).depth(1),
+        Form::mob("wraith",     Icon::Wraith,     3,  &[Flying]).depth(2),
+        Form::mob("salamander", Icon::Salamander, 3,  &[FireImmune]).reptile().depth(3),
+
+        Form::item("sword",     Icon::Sword,     10,  ItemType::MeleeWeapon).rarity(10.0).curse_chance(0.1),
+        Form::item("helmet",    Icon::Helmet,     0,  ItemType::Helmet).armor(1).rarity(5.0).curse_chance(0.1),
+        Form::item("leather armor", Icon::Armor,  0,  ItemType::Armor).armor(2).rarity(5.0).curse_chance(0.1),
+        Form::item("torch",     Icon::Torch,      0,  ItemType::Trinket).light(6).rarity(3.0),
         Form::item("wand of fireball",    Icon::Wand1,     5,  ItemType::TargetedUsable(Fireball)).depth(3),
         Form::item("wand of confusion",   Icon::Wand2,     5,  ItemType::TargetedUsable(Confuse)),
+        Form::item("wand of frost",       Icon::Wand3,     5,  ItemType::TargetedUsable(Freeze)).depth(2),
+        Form::item("wand of poison gas",  Icon::Wand4,     5,  ItemType::TargetedUsable(PoisonGas)).depth(2),
         Form::item("scroll of lightning", Icon::Scroll1,   1,  ItemType::UntargetedUsable(Lightning)),
+        Form::item("scroll of identify",  Icon::Scroll1,   1,  ItemType::UntargetedUsable(Identify)),
+        Form::item("scroll of remove curse", Icon::Scroll1, 1, ItemType::UntargetedUsable(RemoveCurse)).rarity(2.0),
+        Form::item("tome of healing",     Icon::Tome,      0,  ItemType::Spell(Heal)).rarity(5.0),
+        Form::item("tome of lightning",   Icon::Tome,      0,  ItemType::Spell(Lightning)).rarity(5.0).depth(1),
+        Form::item("tome of frost",       Icon::Tome,      0,  ItemType::Spell(Freeze)).rarity(5.0).depth(2),
+        Form::item("tome of fireball",    Icon::Tome,      0,  ItemType::Spell(Fireball)).rarity(5.0).depth(3),
+        Form::item("potion of healing",   Icon::Potion1,   1,  ItemType::Potion(Heal)).rarity(2.0),
+        Form::item("potion of confusion", Icon::Potion2,   1,  ItemType::Potion(Confuse)),
+        Form::item("health orb", Icon::HealthOrb, 0,  ItemType::Instant(Heal)).rarity(2.0),
+        // Keys are placed by mapgen along with locked doors, not spawned randomly.
+        Form::item("key",       Icon::Key,        0,  ItemType::Key).rarity(0.0),
+        Form::item("bag",       Icon::Bag,        0,  ItemType::Container { portable: true }).rarity(5.0),
+        Form::item("chest",     Icon::Chest,      0,  ItemType::Container { portable: false }).rarity(0.0),
+        Form::item("corpse",    Icon::Corpse,     0,  ItemType::Container { portable: false }).rarity(0.0),
+
+        Form::trap("pit trap",      TrapKind::Pit),
+        Form::trap("dart trap",     TrapKind::Dart).depth(2),
+        Form::trap("alarm trap",    TrapKind::Alarm),
+        Form::trap("teleport trap", TrapKind::Teleport).depth(3),
         ]
     };
 }
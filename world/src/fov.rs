@@ -1,6 +1,11 @@
-use calx_grid::{FovValue, HexGeom};
+use calx_grid::{FovValue, HexFov, HexGeom};
 use euclid::Vector2D;
 use location::Location;
+use location_set::LocationSet;
+use query::Query;
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::rc::Rc;
 use terraform::TerrainQuery;
 use world::World;
 
@@ -47,7 +52,7 @@ impl<'a> FovValue for SightFov<'a> {
             ret.origin = dest - offset;
         }
 
-        if self.w.terrain(ret.origin + offset).blocks_sight() {
+        if self.w.blocks_sight(ret.origin + offset) {
             ret.is_edge = true;
         }
 
@@ -107,3 +112,61 @@ impl<'a> FovValue for SphereVolumeFov<'a> {
         Some(ret)
     }
 }
+
+/// Chart positions and the locations they map to in a sight FOV.
+pub type FovCells = Rc<Vec<(Vector2D<i32>, Location)>>;
+
+/// How many FOVs to keep around before the cache is flushed.
+const FOV_CACHE_SIZE: usize = 256;
+
+struct CachedFov {
+    cells: FovCells,
+    /// Locations covered by the FOV, used to check if a change affects it.
+    area: LocationSet,
+}
+
+/// Cache of sight FOVs keyed by origin and range.
+///
+/// A FOV only changes when sight-blocking changes inside it. The blocking cells at the edge of a
+/// FOV are part of it, so dropping the FOVs whose area contains a changed location is enough to
+/// keep the cache valid. Portal changes need the whole cache cleared.
+#[derive(Default)]
+pub struct FovCache {
+    entries: HashMap<(Location, u32), CachedFov>,
+}
+
+impl FovCache {
+    /// Return the cached FOV, computing it if it isn't in the cache.
+    pub fn get(&mut self, w: &World, origin: Location, range: u32) -> FovCells {
+        if let Some(fov) = self.entries.get(&(origin, range)) {
+            return Rc::clone(&fov.cells);
+        }
+
+        let cells: Vec<(Vector2D<i32>, Location)> = HexFov::new(SightFov::new(w, range, origin))
+            .map(|(pos, a)| (pos, a.origin + pos))
+            .collect();
+        let area = LocationSet::from_iter(cells.iter().map(|&(_, loc)| loc));
+        let cells = Rc::new(cells);
+
+        // Mobs all over the map query their FOVs, just start over instead of tracking usage.
+        if self.entries.len() >= FOV_CACHE_SIZE {
+            self.entries.clear();
+        }
+        self.entries.insert(
+            (origin, range),
+            CachedFov {
+                cells: Rc::clone(&cells),
+                area,
+            },
+        );
+
+        cells
+    }
+
+    /// Drop the FOVs that can see the location.
+    pub fn invalidate(&mut self, loc: Location) {
+        self.entries.retain(|_, fov| !fov.area.contains(&loc));
+    }
+
+    pub fn clear(&mut self) { self.entries.clear(); }
+}
@@ -0,0 +1,141 @@
+use form::FORMS;
+use item::ItemType;
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet};
+
+static WAND_APPEARANCES: &'static [&'static str] = &[
+    "oak wand",
+    "bone wand",
+    "iron wand",
+    "glass wand",
+    "copper wand",
+    "ebony wand",
+    "crystal wand",
+    "silver wand",
+];
+
+static SCROLL_APPEARANCES: &'static [&'static str] = &[
+    "runed scroll",
+    "tattered scroll",
+    "vellum scroll",
+    "scroll labeled AMBA TUR",
+    "scroll labeled NOX ELLU",
+    "scroll labeled ISK TOVA",
+    "scroll labeled VORPAL KADO",
+    "scroll labeled OOLA MEX",
+];
+
+static POTION_APPEARANCES: &'static [&'static str] = &[
+    "bubbling potion",
+    "murky potion",
+    "golden potion",
+    "smoky potion",
+    "violet potion",
+    "fizzy potion",
+    "milky potion",
+    "glowing potion",
+];
+
+/// Return the set of appearances items of this type are disguised with.
+///
+/// Item types without an appearance table are always known.
+fn appearance_table(item_type: ItemType) -> Option<&'static [&'static str]> {
+    match item_type {
+        ItemType::TargetedUsable(_) => Some(WAND_APPEARANCES),
+        ItemType::UntargetedUsable(_) => Some(SCROLL_APPEARANCES),
+        ItemType::Potion(_) => Some(POTION_APPEARANCES),
+        _ => None,
+    }
+}
+
+/// What the item kinds of a game look like and which of them the player knows.
+///
+/// Item kinds are identified by their form names.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Identification {
+    /// Unidentified appearances of magic item kinds.
+    appearances: BTreeMap<String, String>,
+    /// Item kinds the player has identified.
+    known: BTreeSet<String>,
+}
+
+impl Identification {
+    /// Assign random appearances to all magic item kinds.
+    pub fn new<R: Rng>(rng: &mut R) -> Identification {
+        let mut appearances = BTreeMap::new();
+
+        for &table in &[WAND_APPEARANCES, SCROLL_APPEARANCES, POTION_APPEARANCES] {
+            let kinds: Vec<&str> = FORMS
+                .iter()
+                .filter(|f| {
+                    f.loadout.item.as_ref().and_then(|i| appearance_table(i.item_type)) ==
+                        Some(table)
+                })
+                .filter_map(|f| f.name())
+                .collect();
+
+            let mut table = table.to_vec();
+            rng.shuffle(&mut table);
+
+            // Kinds that run out of appearances will just be known from the start.
+            for (kind, appearance) in kinds.into_iter().zip(table.into_iter()) {
+                appearances.insert(kind.to_string(), appearance.to_string());
+            }
+        }
+
+        Identification {
+            appearances,
+            known: BTreeSet::new(),
+        }
+    }
+
+    pub fn is_known(&self, kind: &str) -> bool {
+        self.known.contains(kind) || !self.appearances.contains_key(kind)
+    }
+
+    /// Return what an unidentified item of the kind looks like.
+    pub fn appearance(&self, kind: &str) -> Option<&str> {
+        if self.is_known(kind) {
+            None
+        } else {
+            self.appearances.get(kind).map(|s| &s[..])
+        }
+    }
+
+    /// Mark the item kind as known, return whether it was unknown before.
+    pub fn identify(&mut self, kind: &str) -> bool {
+        if self.is_known(kind) {
+            false
+        } else {
+            self.known.insert(kind.to_string());
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Identification;
+    use rand::{SeedableRng, XorShiftRng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_identification() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let mut id = Identification::new(&mut rng);
+
+        let kinds = ["wand of fireball", "wand of confusion", "scroll of lightning"];
+        let appearances: Vec<String> = kinds
+            .iter()
+            .map(|kind| id.appearance(kind).expect("No appearance").to_string())
+            .collect();
+        let unique: HashSet<&String> = appearances.iter().collect();
+        assert_eq!(unique.len(), appearances.len());
+
+        assert!(id.is_known("sword"));
+        assert!(!id.is_known("wand of fireball"));
+        assert!(id.identify("wand of fireball"));
+        assert!(!id.identify("wand of fireball"));
+        assert_eq!(id.appearance("wand of fireball"), None);
+    }
+}
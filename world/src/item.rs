@@ -1,3 +1,4 @@
+use effect::Effect;
 use std::slice;
 
 /// Inventory slots.
@@ -58,6 +59,15 @@ impl Slot {
         }
     }
 
+    /// Return the position of a spell slot in the spellbook.
+    pub fn spell_index(self) -> Option<usize> {
+        if self <= Slot::Spell8 {
+            Some(self as usize - Slot::Spell1 as usize)
+        } else {
+            None
+        }
+    }
+
     pub fn equipped_iter() -> slice::Iter<'static, Slot> {
         use Slot::*;
         static EQUIPPED: [Slot; 17] = [
@@ -135,19 +145,81 @@ pub enum ItemType {
     Boots,
     /// Passive effects when equipped
     Trinket,
-    Spell,
+    /// Tome that teaches a spell when read.
+    Spell(MagicEffect),
     UntargetedUsable(MagicEffect),
     TargetedUsable(MagicEffect),
+    /// Drunk for an effect on yourself, shatters over an area when thrown.
+    Potion(MagicEffect),
     /// Consumed instantly when stepped on.
     Instant(MagicEffect),
+    /// Opens locked doors when carried.
+    Key,
+    /// Holds other items.
+    Container { portable: bool },
 }
 
 #[derive(Copy, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum MagicEffect {
     Heal,
     Confuse,
+    Freeze,
+    PoisonGas,
     Lightning,
     Fireball,
+    Identify,
+    RemoveCurse,
+}
+
+impl MagicEffect {
+    /// Return the effect applied to a mob hit directly by the magic.
+    ///
+    /// Used for instant items that are stepped on and potions that are drunk or shattered.
+    pub fn mob_effect(self) -> Option<Effect> {
+        const INSTANT_HEAL: u32 = 10;
+
+        match self {
+            MagicEffect::Heal => Some(Effect::Heal(INSTANT_HEAL)),
+            MagicEffect::Confuse => Some(Effect::Confuse),
+            _ => None,
+        }
+    }
+
+    /// Return whether the magic needs to be aimed in a direction.
+    pub fn is_directed(self) -> bool {
+        use self::MagicEffect::*;
+        match self {
+            Confuse | Freeze | PoisonGas | Fireball => true,
+            Heal | Lightning | Identify | RemoveCurse => false,
+        }
+    }
+
+    /// Return the name of the magic as a spell.
+    pub fn name(self) -> &'static str {
+        use self::MagicEffect::*;
+        match self {
+            Heal => "healing",
+            Confuse => "confusion",
+            Freeze => "frost",
+            PoisonGas => "poison gas",
+            Lightning => "lightning",
+            Fireball => "fireball",
+            Identify => "identify",
+            RemoveCurse => "remove curse",
+        }
+    }
+
+    /// Return the amount of mana casting the magic as a spell takes.
+    pub fn mana_cost(self) -> i32 {
+        use self::MagicEffect::*;
+        match self {
+            Confuse | Identify => 2,
+            Heal | Lightning => 3,
+            Freeze | PoisonGas => 4,
+            RemoveCurse => 5,
+            Fireball => 6,
+        }
+    }
 }
 
 #[derive(Copy, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
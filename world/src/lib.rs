@@ -28,11 +28,16 @@ macro_rules! msg {
     };
 }
 
+mod affix;
+
+mod cloud;
+pub use cloud::{Cloud, CloudKind};
+
 mod command;
 pub use command::{Command, CommandResult};
 
 mod components;
-pub use components::Icon;
+pub use components::{Appearance, Icon};
 
 mod effect;
 
@@ -47,9 +52,14 @@ pub use form::{Form, FORMS};
 
 mod fov;
 
+mod identify;
+
 mod item;
 pub use item::{Slot, ItemType};
 
+mod light;
+pub use light::DAYLIGHT;
+
 mod location;
 pub use location::{Location, Portal, Sector};
 
@@ -61,6 +71,8 @@ pub use mapfile::{save_prefab, load_prefab};
 mod mutate;
 pub use mutate::Mutate;
 
+mod overworld;
+
 mod query;
 pub use query::Query;
 
@@ -0,0 +1,143 @@
+use location::Location;
+use query::Query;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use terraform::TerrainQuery;
+use volume::Volume;
+use world::World;
+
+/// Light level of cells in full daylight.
+pub const DAYLIGHT: u32 = 16;
+
+/// Light radius of glowing terrain like magma.
+const TERRAIN_LIGHT_RADIUS: u32 = 3;
+
+/// Largest radius a single light source can have.
+///
+/// Keeps the search for light sources that might reach the seen area bounded.
+const MAX_LIGHT_RADIUS: u32 = 12;
+
+/// Light levels for an area of the game world.
+///
+/// The surface level is always in daylight, underground cells are dark unless some light source
+/// shines on them.
+#[derive(Clone, Debug, Default)]
+pub struct LightMap(HashMap<Location, u32>);
+
+impl LightMap {
+    /// Compute the light falling on an area seen from origin.
+    ///
+    /// Entities are treated as light sources if they are in the area or close enough to origin to
+    /// possibly light up cells within range.
+    pub fn new(w: &World, origin: Location, range: u32, area: &HashSet<Location>) -> LightMap {
+        let mut sources: Vec<(Location, u32)> = area.iter()
+            .filter(|&&loc| w.terrain(loc).is_luminous())
+            .map(|&loc| (loc, TERRAIN_LIGHT_RADIUS))
+            .collect();
+
+        // Only entities lying on the map count. Equipped light sources are included in the stats
+        // of whoever is wearing them, ones stashed in a bag or a chest give no light.
+        let mut entities = w.entities_around(origin, range + MAX_LIGHT_RADIUS);
+        // Cells seen through portals can be anywhere, look them up separately.
+        for &loc in area {
+            if loc.z != origin.z || origin.metric_distance(loc) > range as i32 {
+                entities.extend(w.entities_at(loc));
+            }
+        }
+        entities.sort();
+        entities.dedup();
+
+        for e in entities {
+            let radius = w.stats(e).light;
+            if radius <= 0 {
+                continue;
+            }
+            let radius = min(radius as u32, MAX_LIGHT_RADIUS);
+            if let Some(loc) = w.location(e) {
+                if area.contains(&loc) || origin.metric_distance(loc) <= (range + radius) as i32 {
+                    sources.push((loc, radius));
+                }
+            }
+        }
+
+        let mut ret = HashMap::new();
+        for (source, radius) in sources {
+            for loc in Volume::sphere(w, source, radius).0 {
+                let dist = source.metric_distance(loc) as u32;
+                if dist > radius {
+                    continue;
+                }
+                // Light fades out towards the edge of the radius.
+                let level = radius - dist + 1;
+                let current = ret.entry(loc).or_insert(0);
+                if level > *current {
+                    *current = level;
+                }
+            }
+        }
+
+        LightMap(ret)
+    }
+
+    /// Return the light level at location, zero means complete darkness.
+    pub fn get(&self, loc: Location) -> u32 {
+        if loc.z == 0 {
+            DAYLIGHT
+        } else {
+            self.0.get(&loc).cloned().unwrap_or(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LightMap;
+    use euclid::vec2;
+    use form::Form;
+    use item::Slot;
+    use location::Sector;
+    use mutate::Mutate;
+    use query::Query;
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+    use terraform::Terraform;
+    use terrain::Terrain;
+    use world::World;
+
+    #[test]
+    fn test_carried_light() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        world.set_terrain(loc, Terrain::Ground);
+        let area = HashSet::from_iter(world.sphere_volume(loc, 3).0);
+        let is_lit = |world: &World| LightMap::new(world, loc, 3, &area).get(loc) > 0;
+        assert!(!is_lit(&world));
+
+        let torch = world.spawn(&Form::named("torch").unwrap().loadout, loc);
+        assert!(is_lit(&world));
+
+        // A torch in the backpack gives no light, a wielded one does.
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+        world.equip_item(torch, dreg, Slot::InventoryJ);
+        world.regenerate_stats(dreg);
+        assert!(!is_lit(&world));
+        world.equip_item(torch, dreg, Slot::TrinketF);
+        world.regenerate_stats(dreg);
+        assert!(is_lit(&world));
+    }
+
+    #[test]
+    fn test_distant_light() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        let area = HashSet::from_iter(world.sphere_volume(loc, 3).0);
+
+        // Too far away to reach the area.
+        world.spawn(&Form::named("torch").unwrap().loadout, loc + vec2(0, 10));
+        assert_eq!(LightMap::new(&world, loc, 3, &area).get(loc), 0);
+
+        // Shines into the area from outside it.
+        world.spawn(&Form::named("torch").unwrap().loadout, loc + vec2(0, 6));
+        assert!(LightMap::new(&world, loc, 3, &area).get(loc) > 0);
+    }
+}
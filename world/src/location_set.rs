@@ -1,8 +1,11 @@
 use location::Location;
+use serde;
 use std::collections::HashMap;
+use std::collections::hash_map;
+use std::iter::FromIterator;
 
 /// Compact Location set collection
-#[derive(Eq, PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
 pub struct LocationSet {
     /// Chunks of 8x8 locations.
     ///
@@ -10,13 +13,13 @@ pub struct LocationSet {
     /// 6 bits in the Morton code assign the position in the chunk and the higher bits give the
     /// chunk index. Sequences of 6 low bits in 2D Morton coding correspond to 8x8 squares on the
     /// map grid.
-    chunks: HashMap<u32, u64>,
+    chunks: HashMap<u64, u64>,
 }
 
 impl LocationSet {
     /// Return the chunk index and the bit offset for a location.
     #[inline]
-    fn chunk(loc: &Location) -> (u32, u64) {
+    fn chunk(loc: &Location) -> (u64, u64) {
         let morton = loc.to_morton();
         (morton >> 6, 1 << (morton % 64))
     }
@@ -52,4 +55,178 @@ impl LocationSet {
             self.insert(i);
         }
     }
+
+    /// Return the number of locations in the set.
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(|bits| bits.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool { self.chunks.is_empty() }
+
+    /// Iterate the locations in the set in no particular order.
+    pub fn iter(&self) -> Iter {
+        Iter {
+            chunks: self.chunks.iter(),
+            index: 0,
+            bits: 0,
+        }
+    }
+
+    /// Return the set of locations in either set.
+    pub fn union(&self, other: &LocationSet) -> LocationSet {
+        let mut ret = self.clone();
+        for (&index, &bits) in &other.chunks {
+            *ret.chunks.entry(index).or_insert(0) |= bits;
+        }
+        ret
+    }
+
+    /// Return the set of locations in both sets.
+    pub fn intersection(&self, other: &LocationSet) -> LocationSet {
+        LocationSet {
+            chunks: self.chunks
+                .iter()
+                .filter_map(|(&index, &bits)| {
+                    let bits = bits & other.chunks.get(&index).unwrap_or(&0);
+                    if bits == 0 { None } else { Some((index, bits)) }
+                })
+                .collect(),
+        }
+    }
+
+    /// Return the set of locations in this set but not in the other one.
+    pub fn difference(&self, other: &LocationSet) -> LocationSet {
+        LocationSet {
+            chunks: self.chunks
+                .iter()
+                .filter_map(|(&index, &bits)| {
+                    let bits = bits & !other.chunks.get(&index).unwrap_or(&0);
+                    if bits == 0 { None } else { Some((index, bits)) }
+                })
+                .collect(),
+        }
+    }
+
+    /// Return the subset of locations on the given z level.
+    pub fn on_level(&self, z: i16) -> LocationSet {
+        LocationSet {
+            chunks: self.chunks
+                .iter()
+                // Chunk indices are Morton codes shifted by 6, so z starts from bit 26.
+                .filter(|&(&index, _)| (index >> 26) as u16 == z as u16)
+                .map(|(&index, &bits)| (index, bits))
+                .collect(),
+        }
+    }
+}
+
+impl FromIterator<Location> for LocationSet {
+    fn from_iter<I: IntoIterator<Item = Location>>(iter: I) -> Self {
+        let mut ret = LocationSet::default();
+        ret.extend(iter.into_iter());
+        ret
+    }
+}
+
+impl<'a> IntoIterator for &'a LocationSet {
+    type Item = Location;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> { self.iter() }
+}
+
+/// Iterator for the locations in a `LocationSet`.
+pub struct Iter<'a> {
+    chunks: hash_map::Iter<'a, u64, u64>,
+    /// Index of the chunk being iterated.
+    index: u64,
+    /// Remaining bits of the chunk being iterated.
+    bits: u64,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Location;
+
+    fn next(&mut self) -> Option<Location> {
+        while self.bits == 0 {
+            let (&index, &bits) = self.chunks.next()?;
+            self.index = index;
+            self.bits = bits;
+        }
+
+        let offset = self.bits.trailing_zeros() as u64;
+        // Clear the lowest set bit.
+        self.bits &= self.bits - 1;
+        Some(Location::from_morton((self.index << 6) | offset))
+    }
+}
+
+// Chunks are saved keyed with the first location in the chunk instead of the Morton code index so
+// that the save format does not depend on the Morton encoding of Location.
+
+impl serde::Serialize for LocationSet {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut chunks: Vec<(Location, u64)> = self.chunks
+            .iter()
+            .map(|(&index, &bits)| (Location::from_morton(index << 6), bits))
+            .collect();
+        chunks.sort();
+        chunks.serialize(s)
+    }
+}
+
+impl<'a> serde::Deserialize<'a> for LocationSet {
+    fn deserialize<D: serde::Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
+        let chunks: Vec<(Location, u64)> = serde::Deserialize::deserialize(d)?;
+        Ok(LocationSet {
+            chunks: chunks
+                .into_iter()
+                .map(|(loc, bits)| (loc.to_morton() >> 6, bits))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LocationSet;
+    use location::Location;
+    use std::collections::HashSet;
+
+    fn set(locs: &[(i16, i16, i16)]) -> LocationSet {
+        locs.iter().map(|&(x, y, z)| Location::new(x, y, z)).collect()
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut locs = HashSet::new();
+        for y in -20..20 {
+            for x in -20..20 {
+                if (x * 7 + y * 13) % 5 == 0 {
+                    locs.insert(Location::new(x, y, (x % 3) as i16));
+                }
+            }
+        }
+
+        let set: LocationSet = locs.iter().cloned().collect();
+        assert_eq!(set.len(), locs.len());
+        assert_eq!(set.iter().collect::<HashSet<Location>>(), locs);
+    }
+
+    #[test]
+    fn test_algebra() {
+        let a = set(&[(0, 0, 0), (1, 0, 0), (-5, 30, 0), (2, 2, 1)]);
+        let b = set(&[(1, 0, 0), (2, 2, 1), (9, 9, 0)]);
+
+        assert_eq!(
+            a.union(&b),
+            set(&[(0, 0, 0), (1, 0, 0), (-5, 30, 0), (2, 2, 1), (9, 9, 0)])
+        );
+        assert_eq!(a.intersection(&b), set(&[(1, 0, 0), (2, 2, 1)]));
+        assert_eq!(a.difference(&b), set(&[(0, 0, 0), (-5, 30, 0)]));
+        assert!(a.difference(&a).is_empty());
+
+        assert_eq!(a.on_level(1), set(&[(2, 2, 1)]));
+        assert_eq!(set(&[(0, 0, -1), (3, 3, 2)]).on_level(-1), set(&[(0, 0, -1)]));
+    }
 }
@@ -1,18 +1,23 @@
 use {attack_damage, roll};
+use affix;
 use calx_alg::RngExt;
 use calx_ecs::Entity;
 use calx_grid::{Dir6, Prefab};
+use cloud::{Cloud, CloudKind};
 use command::CommandResult;
-use components::{BrainState, Status};
+use components::{BrainState, Status, TrapKind};
 use effect::{Damage, Effect};
 use event::Event;
 use form::Form;
-use item::{MagicEffect, ItemType, Slot};
-use location::Location;
+use identify::Identification;
+use item::{MagicEffect, Slot};
+use location::{Location, SECTOR_HEIGHT, SECTOR_WIDTH};
 use query::Query;
-use rand::{self, Rand};
+use rand::{self, Rand, Rng};
+use stats::Intrinsic;
+use std::cmp;
 use terraform::Terraform;
-use terrain::Terrain;
+use terrain::{Kind, Terrain};
 use volume::Volume;
 use world::{Ecs, Loadout};
 
@@ -27,6 +32,9 @@ pub trait Mutate: Query + Terraform + Sized {
 
     fn equip_item(&mut self, e: Entity, parent: Entity, slot: Slot);
 
+    /// Put an entity inside a container entity.
+    fn put_in(&mut self, e: Entity, container: Entity);
+
     fn set_player(&mut self, player: Option<Entity>);
 
     /// Mark an entity as dead, but don't remove it from the system yet.
@@ -43,6 +51,9 @@ pub trait Mutate: Query + Terraform + Sized {
     /// Does nothing for entities without a map memory component.
     fn do_fov(&mut self, e: Entity);
 
+    /// Set or clear the cloud at location.
+    fn set_cloud(&mut self, loc: Location, cloud: Option<Cloud>);
+
     /// Push an event to the event queue for this tick.
     fn push_event(&mut self, event: Event);
 
@@ -52,6 +63,9 @@ pub trait Mutate: Query + Terraform + Sized {
     /// Mutable access to ecs
     fn ecs_mut(&mut self) -> &mut Ecs;
 
+    /// Mutable access to item identification.
+    fn identification_mut(&mut self) -> &mut Identification;
+
     /// Run AI for all autonomous mobs.
     fn ai_main(&mut self) {
         for npc in self.active_mobs() {
@@ -68,33 +82,15 @@ pub trait Mutate: Query + Terraform + Sized {
 
     /// Run AI for one non-player-controlled creature.
     fn run_ai_for(&mut self, npc: Entity) {
-        const WAKEUP_DISTANCE: i32 = 5;
-
         use components::BrainState::*;
         let brain_state = self.brain_state(npc).expect("Running AI for non-mob");
         match brain_state {
             Asleep => {
                 // XXX: Only treat player mob as potential hostile.
                 // Can't model area conflict effects yet.
-                if let (Some(loc), Some(player), Some(player_loc)) =
-                    (
-                        self.location(npc),
-                        self.player(),
-                        self.player().map(|p| self.location(p)).unwrap_or(None),
-                    )
-                {
-                    if self.player_sees(loc) {
-                        // Okay, tricky spot. Player might be seeing mob across a portal, in
-                        // which case we can't do naive distance check.
-                        // This could have a helper method that finds chart distance to self in
-                        // player's map memory.
-                        //
-                        // For now, let's just go with mobs past portals not waking up to
-                        // player.
-
-                        if loc.metric_distance(player_loc) <= WAKEUP_DISTANCE {
-                            self.designate_enemy(npc, player);
-                        }
+                if let Some(player) = self.player() {
+                    if self.notices(npc, player) {
+                        self.designate_enemy(npc, player);
                     }
                 }
             }
@@ -113,6 +109,29 @@ pub trait Mutate: Query + Terraform + Sized {
         }
     }
 
+    /// Return whether a mob notices a potential target this turn.
+    ///
+    /// Stealthy targets can go unnoticed even when they are in plain view.
+    fn notices(&mut self, e: Entity, target: Entity) -> bool {
+        // Every this many cells of distance make the target one point harder to notice.
+        const NOTICE_FALLOFF: i32 = 3;
+
+        let (loc, target_loc) = match (self.location(e), self.location(target)) {
+            (Some(loc), Some(target_loc)) => (loc, target_loc),
+            _ => return false,
+        };
+
+        // How far the mob can see depends on its perception. Metric distance doesn't work across
+        // portals, so mobs past portals won't notice anything.
+        if !self.can_see(e, target_loc) {
+            return false;
+        }
+
+        let difficulty = self.stats(target).stealth +
+            loc.metric_distance(target_loc) / NOTICE_FALLOFF - self.stats(e).perception;
+        difficulty <= 0 || self.rng().one_chance_in(difficulty as u32 + 1)
+    }
+
     fn notify_attacked_by(&mut self, victim: Entity, attacker: Entity) {
         // TODO: Check if victim is already in close combat and don't disengage against new target
         // if it is.
@@ -169,15 +188,217 @@ pub trait Mutate: Query + Terraform + Sized {
         }
     }
 
-    fn place_entity(&mut self, e: Entity, mut loc: Location) {
+    /// Place an entity on the map.
+    ///
+    /// Returns the entity that ended up on the map. This is the entity itself, unless it was an
+    /// item that got merged into a stack already at the location, in which case the placed
+    /// entity is destroyed and the stack is returned.
+    fn place_entity(&mut self, e: Entity, mut loc: Location) -> Entity {
         if self.is_item(e) {
+            // Items dropped on a matching stack join it instead of spreading out.
+            if let Some(stack) = self.item_at(loc) {
+                if self.can_stack(stack, e) {
+                    self.merge_stack(stack, e);
+                    return stack;
+                }
+            }
             loc = self.empty_item_drop_location(loc);
         }
         self.set_entity_location(e, loc);
         self.after_entity_moved(e);
+        e
+    }
+
+    fn after_entity_moved(&mut self, e: Entity) {
+        self.do_fov(e);
+        if self.is_mob(e) {
+            const SPOT_HIDDEN_ONE_IN: u32 = 5;
+            self.apply_terrain_hazards(e);
+            self.detect_hidden(e, SPOT_HIDDEN_ONE_IN);
+        }
+    }
+
+    /// Have a mob look for hidden things next to it.
+    ///
+    /// Each hidden thing is found with a one in `chance` probability.
+    fn detect_hidden(&mut self, e: Entity, chance: u32) {
+        if !self.is_player(e) {
+            // Only the player keeps track of found things.
+            return;
+        }
+        let loc = match self.location(e) {
+            Some(loc) => loc,
+            None => return,
+        };
+
+        for &dir in Dir6::iter() {
+            let loc = loc.jump(self, dir);
+            if let Some(trap) = self.trap_at(loc) {
+                if !self.player_knows_trap(loc) && self.rng().one_chance_in(chance) {
+                    msg!(self, "Found a {}.", self.entity_name(trap));
+                    self.reveal_trap(loc);
+                }
+            }
+
+            if self.terrain(loc) == Terrain::SecretDoor && self.rng().one_chance_in(chance) {
+                msg!(self, "Found a secret door.");
+                self.set_terrain(loc, Terrain::Door);
+                self.terrain_changed();
+            }
+        }
+    }
+
+    /// Add trap at location to the player's map memory.
+    fn reveal_trap(&mut self, loc: Location) {
+        if let Some(player) = self.player() {
+            if let Some(memory) = self.ecs_mut().map_memory.get_mut(player) {
+                memory.traps.insert(loc);
+            }
+        }
+    }
+
+    /// Use up the instant items at location on a mob that stepped there.
+    fn trigger_instants(&mut self, e: Entity, loc: Location) {
+        if !self.is_scavenger(e) {
+            return;
+        }
+
+        let items: Vec<(Entity, Effect)> = self.entities_at(loc)
+            .into_iter()
+            .filter_map(|item| self.instant_effect(item).map(|effect| (item, effect)))
+            .collect();
+
+        for (item, effect) in items {
+            if self.player_sees(loc) {
+                msg!(self, "The {} uses up the {}.", self.entity_name(e), self.entity_name(item));
+            }
+            self.apply_effect_to_entity(&effect, e, None);
+            self.kill_entity(item);
+        }
+    }
+
+    /// Set off a trap at location if there is one.
+    fn trigger_trap(&mut self, e: Entity, loc: Location) {
+        const PIT_DAMAGE: i32 = 4;
+        const DART_EFFECT: Effect = Effect::Hit {
+            amount: 3,
+            damage: Damage::Poison,
+        };
+        const ALARM_RADIUS: u32 = 12;
+
+        let trap = match self.trap_at(loc) {
+            Some(trap) => trap,
+            None => return,
+        };
+        if self.has_intrinsic(e, Intrinsic::Flying) {
+            // Flyers don't put any weight on the trigger.
+            return;
+        }
+
+        if self.player_sees(loc) {
+            msg!(
+                self,
+                "The {} triggers a {}.",
+                self.entity_name(e),
+                self.entity_name(trap)
+            );
+            self.reveal_trap(loc);
+        }
+
+        match self.ecs().trap[trap].kind {
+            TrapKind::Pit => {
+                self.damage(e, PIT_DAMAGE, Damage::Physical, None);
+            }
+            TrapKind::Dart => {
+                self.apply_effect_to_entity(&DART_EFFECT, e, None);
+            }
+            TrapKind::Alarm => {
+                msg!(self, "A loud bell rings.");
+                self.make_noise(loc, ALARM_RADIUS, e);
+            }
+            TrapKind::Teleport => {
+                let sector = loc.sector();
+                for _ in 0..100 {
+                    let (u, v) = (
+                        self.rng().gen_range(0, SECTOR_WIDTH),
+                        self.rng().gen_range(0, SECTOR_HEIGHT),
+                    );
+                    let dest = sector.rect_coord_loc(u, v);
+                    if self.can_enter(e, dest) && !self.terrain(dest).is_door() &&
+                        self.trap_at(dest).is_none()
+                    {
+                        self.place_entity(e, dest);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Make a noise that wakes up mobs within radius that are hostile to the culprit.
+    fn make_noise(&mut self, origin: Location, radius: u32, culprit: Entity) {
+        let mobs: Vec<Entity> = self.sphere_volume(origin, radius)
+            .0
+            .into_iter()
+            .filter_map(|loc| self.mob_at(loc))
+            .filter(|&mob| {
+                self.brain_state(mob) == Some(BrainState::Asleep) &&
+                    self.is_hostile_to(mob, culprit)
+            })
+            .collect();
+
+        for mob in mobs {
+            self.designate_enemy(mob, culprit);
+        }
+    }
+
+    /// Apply the effects of dangerous terrain to a mob standing on it.
+    fn apply_terrain_hazards(&mut self, e: Entity) {
+        const MAGMA_DAMAGE: i32 = 10;
+
+        let loc = match self.location(e) {
+            Some(loc) => loc,
+            None => return,
+        };
+
+        if self.is_swimming(e) {
+            // Heavy gear would drag the swimmer down.
+            for &slot in Slot::iter() {
+                if let Some(item) = self.entity_equipped(e, slot) {
+                    if self.is_heavy(item) && !self.is_stuck(e, slot) {
+                        if self.is_player(e) {
+                            msg!(self, "Dropped {} to stay afloat.", self.entity_name(item));
+                        }
+                        self.place_entity(item, loc);
+                    }
+                }
+            }
+        }
+
+        if self.terrain(loc).kind() == Kind::Magma && !self.has_intrinsic(e, Intrinsic::Flying) {
+            self.damage(e, MAGMA_DAMAGE, Damage::Fire, None);
+        }
     }
 
-    fn after_entity_moved(&mut self, e: Entity) { self.do_fov(e); }
+    /// Push a mob one step in a direction.
+    ///
+    /// Mobs can be knocked into water and magma that they wouldn't walk into themselves.
+    fn knock_back(&mut self, e: Entity, dir: Dir6) {
+        let loc = match self.location(e) {
+            Some(loc) => loc.jump(self, dir),
+            None => return,
+        };
+        let terrain = self.terrain(loc);
+        if !terrain.is_deep_liquid() && (terrain.blocks_walk() || terrain.is_door()) {
+            return;
+        }
+        if !self.is_valid_location(loc) || self.has_blocking_entity(loc) {
+            return;
+        }
+
+        msg!(self, "The {} is knocked back.", self.entity_name(e));
+        self.place_entity(e, loc);
+    }
 
     fn entity_step(&mut self, e: Entity, dir: Dir6) -> Result<(), ()> {
         if self.confused_move(e) {
@@ -185,13 +406,48 @@ pub trait Mutate: Query + Terraform + Sized {
         }
         let loc = self.location(e).ok_or(())?.jump(self, dir);
         if self.can_enter(e, loc) {
-            self.place_entity(e, loc);
+            if self.terrain(loc).is_door() {
+                // Bumping into a closed door opens it, the mob stays where it was.
+                self.open_door(e, loc);
+            } else {
+                self.place_entity(e, loc);
+                self.trigger_trap(e, loc);
+                self.trigger_instants(e, loc);
+            }
             return Ok(());
         }
 
         Err(())
     }
 
+    /// Open a closed or locked door.
+    ///
+    /// Locked doors need the entity to carry a key, but the key is not used up.
+    fn open_door(&mut self, e: Entity, loc: Location) {
+        if self.terrain(loc) == Terrain::LockedDoor && self.is_player(e) {
+            msg!(self, "Unlocked the door.");
+        }
+        self.set_terrain(loc, Terrain::OpenDoor);
+        self.terrain_changed();
+    }
+
+    fn entity_close_door(&mut self, e: Entity, dir: Dir6) -> Result<(), ()> {
+        let loc = self.location(e).ok_or(())?.jump(self, dir);
+        if !self.can_close_door(e, loc) {
+            return Err(());
+        }
+        self.set_terrain(loc, Terrain::Door);
+        self.terrain_changed();
+        Ok(())
+    }
+
+    /// Update things that depend on terrain after the terrain has been modified.
+    fn terrain_changed(&mut self) {
+        if let Some(player) = self.player() {
+            self.do_fov(player);
+        }
+    }
+
     fn entity_melee(&mut self, e: Entity, dir: Dir6) -> Result<(), ()> {
         if self.confused_move(e) {
             return Ok(());
@@ -222,6 +478,13 @@ pub trait Mutate: Query + Terraform + Sized {
                     );
                 }
                 self.damage(target, damage, Damage::Physical, Some(e));
+
+                // Much stronger attackers send their targets flying.
+                if damage > 0 && self.is_alive(target) &&
+                    self.stats(e).power >= 2 * self.stats(target).power
+                {
+                    self.knock_back(target, dir);
+                }
                 return Ok(());
             }
         }
@@ -263,6 +526,10 @@ pub trait Mutate: Query + Terraform + Sized {
             self.notify_attacked_by(e, attacker);
         }
 
+        if damage_type == Damage::Fire && self.has_intrinsic(e, Intrinsic::FireImmune) {
+            return;
+        }
+
         let max_hp = self.max_hp(e);
 
         let mut kill = false;
@@ -289,16 +556,39 @@ pub trait Mutate: Query + Terraform + Sized {
                             Damage::Fire => "is burned to ash",
                             Damage::Electricity => "is electrocuted",
                             Damage::Cold => "shatters to frozen pieces",
+                            Damage::Poison => "chokes to death",
                         }
                     );
                 }
+                self.leave_corpse(e, loc);
             }
             self.kill_entity(e);
         }
     }
 
+    /// Create a new entity and place it at location.
+    ///
+    /// Returns the entity on the map, see `place_entity`.
     fn spawn(&mut self, loadout: &Loadout, loc: Location) -> Entity;
 
+    /// Move the items a dying mob carries into a corpse at location.
+    fn leave_corpse(&mut self, e: Entity, loc: Location) {
+        if self.is_player(e) {
+            return;
+        }
+
+        let items = self.entities_in(e);
+        if items.is_empty() {
+            return;
+        }
+
+        let form = Form::named("corpse").expect("No corpse form");
+        let corpse = self.spawn(&form.loadout, loc);
+        for item in items {
+            self.put_in(item, corpse);
+        }
+    }
+
     fn deploy_prefab(&mut self, origin: Location, prefab: &Prefab<(Terrain, Vec<String>)>) {
         for (p, &(ref terrain, _)) in prefab.iter() {
             let loc = origin + p;
@@ -348,6 +638,38 @@ pub trait Mutate: Query + Terraform + Sized {
         }
     }
 
+    /// Move an item from the mob's inventory slot into a container.
+    fn entity_store(&mut self, e: Entity, slot: Slot, container: Entity) -> Result<(), ()> {
+        let item = self.entity_equipped(e, slot).ok_or(())?;
+
+        if self.is_stuck(e, slot) {
+            if self.is_player(e) {
+                self.reveal_curse(item);
+                msg!(self, "You can't remove the {}.", self.entity_name(item));
+            }
+            return Err(());
+        }
+
+        // Don't put containers inside themselves.
+        if item == container || self.entity_contains(item, container) {
+            return Err(());
+        }
+
+        self.put_in(item, container);
+        if slot.is_equipment_slot() {
+            self.regenerate_stats(e);
+        }
+        if self.is_player(e) {
+            msg!(
+                self,
+                "Put {} in the {}.",
+                self.entity_name(item),
+                self.entity_name(container)
+            );
+        }
+        Ok(())
+    }
+
     fn entity_take(&mut self, e: Entity, item: Entity) -> Result<(), ()> {
         // Only mobs can take items.
         if !self.is_mob(e) {
@@ -358,12 +680,28 @@ pub trait Mutate: Query + Terraform + Sized {
             return Err(());
         }
 
+        // Instant items are used when stepped on, not carried.
+        if self.instant_effect(item).is_some() {
+            return Err(());
+        }
+
+        if !self.is_portable(item) {
+            return Err(());
+        }
+
         // Somehow trying to pick up something we're inside of. Pls don't break the universe.
         if self.entity_contains(item, e) {
             panic!("Trying to pick up an entity you are inside of. This shouldn't happen");
         }
 
-        if let Some(slot) = self.free_bag_slot(e) {
+        if let Some(stack) = self.carried_stack_for(e, item) {
+            if self.is_player(e) {
+                msg!(self, "Picked up {}", self.entity_name(item));
+            }
+            self.merge_stack(stack, item);
+
+            Ok(())
+        } else if let Some(slot) = self.free_bag_slot(e) {
             self.equip_item(item, e, slot);
             if self.is_player(e) {
                 msg!(self, "Picked up {}", self.entity_name(item));
@@ -377,49 +715,100 @@ pub trait Mutate: Query + Terraform + Sized {
     }
 
     /// Cast an undirected spell
+    ///
+    /// The effect item is the usable item the effect comes from, `None` for a learned spell.
     fn cast_spell(
         &mut self,
         origin: Location,
-        effect: Entity,
+        effect: MagicEffect,
+        effect_item: Option<Entity>,
         caster: Option<Entity>,
-    ) -> Result<(), ()> {
-        if let ItemType::UntargetedUsable(effect) =
-            self.ecs().item.get(effect).ok_or(())?.item_type
-        {
-            match effect {
-                MagicEffect::Lightning => {
-                    const LIGHTNING_RANGE: u32 = 4;
-                    const LIGHTNING_EFFECT: Effect = Effect::Hit {
-                        amount: 12,
-                        damage: Damage::Electricity,
-                    };
-
-                    // TODO: Make an API, more efficient lookup of entities within an area
-
-                    let targets: Vec<Entity> = self.sphere_volume(origin, LIGHTNING_RANGE)
+    ) {
+        match effect {
+            MagicEffect::Lightning => {
+                const LIGHTNING_RANGE: u32 = 4;
+                const LIGHTNING_EFFECT: Effect = Effect::Hit {
+                    amount: 12,
+                    damage: Damage::Electricity,
+                };
+
+                // TODO: Make an API, more efficient lookup of entities within an area
+
+                let targets: Vec<Entity> = self.sphere_volume(origin, LIGHTNING_RANGE)
+                    .0
+                    .into_iter()
+                    .flat_map(|loc| self.entities_at(loc))
+                    .filter(|&e| self.is_mob(e) && Some(e) != caster)
+                    .collect();
+
+                let mut target = rand::sample(self.rng(), &targets, 1);
+
+                if let Some(target) = target.pop() {
+                    msg!(self, "There is a peal of thunder.");
+                    let loc = self.location(*target).unwrap();
+                    self.apply_effect(&LIGHTNING_EFFECT, &Volume::point(loc), caster);
+                } else {
+                    // No mobs around, strike some rock instead.
+                    let rocks: Vec<Location> = self.sphere_volume(origin, LIGHTNING_RANGE)
                         .0
                         .into_iter()
-                        .flat_map(|loc| self.entities_at(loc))
-                        .filter(|&e| self.is_mob(e) && Some(e) != caster)
+                        .filter(|&loc| {
+                            self.terrain(loc) == Terrain::Rock && !loc.is_sector_edge()
+                        })
                         .collect();
 
-                    let mut target = rand::sample(self.rng(), &targets, 1);
-
-                    if let Some(target) = target.pop() {
+                    if let Some(&loc) = rand::sample(self.rng(), &rocks, 1).pop() {
                         msg!(self, "There is a peal of thunder.");
-                        let loc = self.location(*target).unwrap();
                         self.apply_effect(&LIGHTNING_EFFECT, &Volume::point(loc), caster);
                     } else {
                         msg!(self, "The spell fizzles.");
                     }
                 }
-                _ => {
-                    msg!(self, "TODO cast untargeted spell {:?}", effect);
+            }
+            MagicEffect::Heal => {
+                if let (Some(caster), Some(heal)) = (caster, effect.mob_effect()) {
+                    if self.is_player(caster) {
+                        msg!(self, "You feel better.");
+                    }
+                    self.apply_effect_to_entity(&heal, caster, None);
                 }
             }
-            Ok(())
-        } else {
-            Err(())
+            MagicEffect::RemoveCurse => {
+                let items: Vec<Entity> = caster.map_or_else(Vec::new, |caster| {
+                    Slot::iter()
+                        .filter_map(|&slot| self.entity_equipped(caster, slot))
+                        .filter(|&item| self.is_cursed(item))
+                        .collect()
+                });
+
+                if items.is_empty() {
+                    msg!(self, "Nothing happens.");
+                }
+                for item in items {
+                    self.uncurse(item);
+                    msg!(self, "The {} is no longer cursed.", self.entity_name(item));
+                }
+                if let Some(caster) = caster {
+                    self.regenerate_stats(caster);
+                }
+            }
+            MagicEffect::Identify => {
+                // Identify the first unknown item the caster carries.
+                let item = caster.and_then(|caster| {
+                    Slot::iter()
+                        .filter_map(|&slot| self.entity_equipped(caster, slot))
+                        .find(|&item| Some(item) != effect_item && !self.is_identified(item))
+                });
+
+                if let Some(item) = item {
+                    self.identify_item(item);
+                } else {
+                    msg!(self, "You learn nothing new.");
+                }
+            }
+            _ => {
+                msg!(self, "TODO cast untargeted spell {:?}", effect);
+            }
         }
     }
 
@@ -428,43 +817,58 @@ pub trait Mutate: Query + Terraform + Sized {
         &mut self,
         origin: Location,
         dir: Dir6,
-        effect: Entity,
+        effect: MagicEffect,
         caster: Option<Entity>,
-    ) -> Result<(), ()> {
-        if let ItemType::TargetedUsable(effect) = self.ecs().item.get(effect).ok_or(())?.item_type {
-            match effect {
-                MagicEffect::Fireball => {
-                    const FIREBALL_RANGE: u32 = 9;
-                    const FIREBALL_RADIUS: u32 = 2;
-                    const FIREBALL_EFFECT: Effect = Effect::Hit {
-                        amount: 6,
-                        damage: Damage::Fire,
-                    };
-                    let center = self.projected_explosion_center(origin, dir, FIREBALL_RANGE);
-                    let volume = self.sphere_volume(center, FIREBALL_RADIUS);
-                    self.apply_effect(&FIREBALL_EFFECT, &volume, caster);
-                }
-                MagicEffect::Confuse => {
-                    const CONFUSION_RANGE: u32 = 9;
+    ) {
+        match effect {
+            MagicEffect::Fireball => {
+                const FIREBALL_RANGE: u32 = 9;
+                const FIREBALL_RADIUS: u32 = 2;
+                const FIREBALL_EFFECT: Effect = Effect::Hit {
+                    amount: 6,
+                    damage: Damage::Fire,
+                };
+                let center = self.projected_explosion_center(origin, dir, FIREBALL_RANGE);
+                let volume = self.sphere_volume(center, FIREBALL_RADIUS);
+                self.apply_effect(&FIREBALL_EFFECT, &volume, caster);
+            }
+            MagicEffect::Freeze => {
+                const FROST_RANGE: u32 = 9;
+                const FROST_RADIUS: u32 = 1;
+                const FROST_EFFECT: Effect = Effect::Hit {
+                    amount: 8,
+                    damage: Damage::Cold,
+                };
+                let center = self.projected_explosion_center(origin, dir, FROST_RANGE);
+                let volume = self.sphere_volume(center, FROST_RADIUS);
+                self.apply_effect(&FROST_EFFECT, &volume, caster);
+            }
+            MagicEffect::PoisonGas => {
+                const GAS_RANGE: u32 = 9;
+                const GAS_DENSITY: u8 = 12;
 
-                    let center = self.projected_explosion_center(origin, dir, CONFUSION_RANGE);
-                    self.apply_effect(&Effect::Confuse, &Volume::point(center), caster);
-                }
-                _ => {
-                    msg!(self, "TODO cast directed spell {:?}", effect);
-                }
+                let center = self.projected_explosion_center(origin, dir, GAS_RANGE);
+                self.set_cloud(center, Some(Cloud::new(CloudKind::Gas, GAS_DENSITY)));
+            }
+            MagicEffect::Confuse => {
+                const CONFUSION_RANGE: u32 = 9;
+
+                let center = self.projected_explosion_center(origin, dir, CONFUSION_RANGE);
+                self.apply_effect(&Effect::Confuse, &Volume::point(center), caster);
+            }
+            _ => {
+                msg!(self, "TODO cast directed spell {:?}", effect);
             }
-            Ok(())
-        } else {
-            Err(())
         }
     }
 
     fn apply_effect_to_entity(&mut self, effect: &Effect, target: Entity, source: Option<Entity>) {
         use effect::Effect::*;
         match *effect {
-            Heal(_amount) => {
-                unimplemented!();
+            Heal(amount) => {
+                if let Some(health) = self.ecs_mut().health.get_mut(target) {
+                    health.wounds = cmp::max(0, health.wounds - amount as i32);
+                }
             }
             Hit { amount, damage } => {
                 self.damage(target, amount as i32, damage, source);
@@ -482,15 +886,168 @@ pub trait Mutate: Query + Terraform + Sized {
         if let Some(mob) = self.mob_at(loc) {
             self.apply_effect_to_entity(effect, mob, source);
         }
+
+        if let Effect::Hit { damage, .. } = *effect {
+            let terrain = self.terrain(loc);
+            // Damaging sector edges could open up a way out of the map.
+            if let (Some(new_terrain), false) = (terrain.damaged(damage), loc.is_sector_edge()) {
+                self.set_terrain(loc, new_terrain);
+
+                if damage == Damage::Fire && self.cloud(loc).is_none() {
+                    // Burning terrain catches fire. Spreading fire keeps its own density so
+                    // that it will eventually burn out.
+                    const FIRE_DENSITY: u8 = 10;
+                    self.set_cloud(loc, Some(Cloud::new(CloudKind::Fire, FIRE_DENSITY)));
+                }
+            }
+        }
     }
 
     fn apply_effect(&mut self, effect: &Effect, volume: &Volume, source: Option<Entity>) {
         for loc in &volume.0 {
             self.apply_effect_to(effect, *loc, source);
         }
+        self.terrain_changed();
+    }
+
+    /// Let the player know that the item is cursed.
+    fn reveal_curse(&mut self, item: Entity) {
+        if let Some(i) = self.ecs_mut().item.get_mut(item) {
+            i.curse_known = true;
+        }
+    }
+
+    /// Lift the curse from an item, restoring its regular stats.
+    fn uncurse(&mut self, item: Entity) {
+        let mut stats = self.spawn_name(item).and_then(Form::named).and_then(
+            |form| form.loadout.stats,
+        );
+
+        if let Some(i) = self.ecs_mut().item.get_mut(item) {
+            i.cursed = false;
+            if let Some(ref mut stats) = stats {
+                let bonus = affix::stats(&i.affixes, i.item_type);
+                stats.base = stats.base + bonus;
+                stats.actual = stats.actual + bonus;
+            }
+        }
+        if let (Some(stats), Some(s)) = (stats, self.ecs_mut().stats.get_mut(item)) {
+            *s = stats;
+        }
+    }
+
+    /// Make the player know the kind of the item.
+    fn identify_item(&mut self, item: Entity) {
+        let name = match self.spawn_name(item) {
+            Some(name) => name.to_string(),
+            None => return,
+        };
+
+        if self.identification_mut().identify(&name) {
+            msg!(self, "Identified {}.", name);
+        }
+    }
+
+    /// Move the items of stack `item` into `stack` and destroy `item`.
+    fn merge_stack(&mut self, stack: Entity, item: Entity) {
+        debug_assert!(self.can_stack(stack, item));
+        let count = self.item_count(item);
+        if let Some(i) = self.ecs_mut().item.get_mut(stack) {
+            i.count += count;
+        }
+        self.kill_entity(item);
+    }
+
+    /// Take a single item off a stack as a new entity that isn't placed anywhere yet.
+    ///
+    /// Returns the new item entity, or `None` if there was only one item in the stack.
+    fn unstack(&mut self, stack: Entity) -> Option<Entity> {
+        if self.item_count(stack) < 2 {
+            return None;
+        }
+
+        let form = Form::named(self.spawn_name(stack).expect("Stacked item has no spawn name"))
+            .expect("Stacked item has no form");
+        let mut loadout = form.loadout.clone();
+        let mut split = self.ecs().item[stack].clone();
+        split.count = 1;
+        loadout.item = Some(split);
+        if let Some(i) = self.ecs_mut().item.get_mut(stack) {
+            i.count -= 1;
+        }
+
+        Some(loadout.make(self.ecs_mut()))
+    }
+
+    /// Take a single item off a stack and place it at location.
+    ///
+    /// Returns the item or stack of items the split item ended up in at location, or `None` if
+    /// there was only one item in the stack.
+    fn split_stack(&mut self, stack: Entity, loc: Location) -> Option<Entity> {
+        self.unstack(stack).map(|item| self.place_entity(item, loc))
+    }
+
+    /// Send an item flying from origin in a direction.
+    ///
+    /// The item hits the first mob in its path and lands there. Potions shatter and splash their
+    /// effect around the spot they hit.
+    fn throw_item(&mut self, item: Entity, origin: Location, dir: Dir6, thrower: Option<Entity>) {
+        const THROW_RANGE: u32 = 8;
+        const SPLASH_RADIUS: u32 = 1;
+        // Even a thrown rock should hurt a bit.
+        const BASE_THROW_DAMAGE: i32 = 2;
+
+        let target = self.projected_explosion_center(origin, dir, THROW_RANGE);
+
+        if let Some(effect) = self.potion_effect(item) {
+            msg!(self, "The {} shatters.", self.entity_name(item));
+            self.identify_item(item);
+            let volume = self.sphere_volume(target, SPLASH_RADIUS);
+            self.apply_effect(&effect, &volume, thrower);
+            self.kill_entity(item);
+            return;
+        }
+
+        if let Some(mob) = self.mob_at(target) {
+            let attack = thrower.map_or(0, |e| self.stats(e).attack) + self.stats(item).attack;
+            let advantage = attack - self.stats(mob).defense + 2 * self.stats(mob).armor;
+            let damage = attack_damage(
+                roll(self.rng()),
+                advantage,
+                BASE_THROW_DAMAGE + self.stats(item).power,
+            );
+
+            if damage == 0 {
+                msg!(
+                    self,
+                    "The {} misses {}.",
+                    self.entity_name(item),
+                    self.entity_name(mob)
+                );
+            } else {
+                msg!(
+                    self,
+                    "The {} hits {} for {}.",
+                    self.entity_name(item),
+                    self.entity_name(mob),
+                    damage
+                );
+            }
+            self.damage(mob, damage, Damage::Physical, thrower);
+        }
+
+        self.place_entity(item, target);
     }
 
     fn drain_charge(&mut self, item: Entity) {
+        // Consumables in a stack are used up one item at a time.
+        if self.item_count(item) > 1 && self.destroy_after_use(item) {
+            if let Some(i) = self.ecs_mut().item.get_mut(item) {
+                i.count -= 1;
+            }
+            return;
+        }
+
         let mut emptied = false;
         if let Some(i) = self.ecs_mut().item.get_mut(item) {
             if i.charges > 0 {
@@ -513,7 +1070,49 @@ pub trait Mutate: Query + Terraform + Sized {
     ///
     /// This runs regardless of the action speed or awakeness status of the entity. The exact same
     /// is run for player and AI entities.
-    fn heartbeat(&mut self, e: Entity) { self.tick_statuses(e); }
+    fn heartbeat(&mut self, e: Entity) {
+        self.tick_statuses(e);
+        self.regenerate_mana(e);
+        if self.location(e).map_or(false, |loc| self.terrain(loc).kind() == Kind::Magma) {
+            self.apply_terrain_hazards(e);
+        }
+    }
+
+    /// Add a spell to the mob's spellbook.
+    ///
+    /// Fails if the mob can't learn spells, already knows the spell or has no free spell slots.
+    fn learn_spell(&mut self, e: Entity, effect: MagicEffect) -> Result<(), ()> {
+        const MAX_SPELLS: usize = 8;
+
+        let spellbook = self.ecs_mut().spellbook.get_mut(e).ok_or(())?;
+        if spellbook.spells.len() >= MAX_SPELLS || spellbook.spells.contains(&effect) {
+            return Err(());
+        }
+        spellbook.spells.push(effect);
+        Ok(())
+    }
+
+    /// Spend mana for a spell, fail if there isn't enough mana left.
+    fn spend_mana(&mut self, e: Entity, amount: i32) -> Result<(), ()> {
+        if self.mana(e) < amount {
+            return Err(());
+        }
+        let health = self.ecs_mut().health.get_mut(e).ok_or(())?;
+        health.spent_mana += amount;
+        Ok(())
+    }
+
+    fn regenerate_mana(&mut self, e: Entity) {
+        // Frames between regaining a point of mana.
+        const MANA_REGEN_INTERVAL: u64 = 10;
+
+        if self.tick() % MANA_REGEN_INTERVAL != 0 {
+            return;
+        }
+        if let Some(health) = self.ecs_mut().health.get_mut(e) {
+            health.spent_mana = cmp::max(0, health.spent_mana - 1);
+        }
+    }
 
     fn gain_status(&mut self, e: Entity, status: Status, duration: u32) {
         if duration == 0 {
@@ -567,3 +1166,552 @@ pub trait Mutate: Query + Terraform + Sized {
         self.ecs_mut().stats[e].actual = stats;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use calx_grid::Dir6;
+    use command::Command;
+    use components::Status;
+    use effect::{Damage, Effect};
+    use form::Form;
+    use item::Slot;
+    use location::{Location, Sector};
+    use mutate::Mutate;
+    use query::Query;
+    use terraform::{Terraform, TerrainQuery};
+    use terrain::Terrain;
+    use world::{Loadout, World};
+
+    #[test]
+    fn test_bump_opens_door() {
+        let mut world = World::new(1);
+        // Nothing gets generated in the empty sectors between dungeons.
+        let loc = Sector::new(1, 3, 4).center();
+        let door = loc + Dir6::North;
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(door, Terrain::Door);
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+
+        // The first step opens the door, the second one goes through it.
+        assert!(world.entity_step(dreg, Dir6::North).is_ok());
+        assert_eq!(world.terrain(door), Terrain::OpenDoor);
+        assert_eq!(world.location(dreg), Some(loc));
+        assert!(world.entity_step(dreg, Dir6::North).is_ok());
+        assert_eq!(world.location(dreg), Some(door));
+
+        // Mobs without hands can't open doors.
+        let snake_door = loc + Dir6::South;
+        world.set_terrain(snake_door, Terrain::Door);
+        let snake = world.spawn(&Form::named("snake").unwrap().loadout, loc);
+        assert!(!world.can_step(snake, Dir6::South));
+        assert!(world.entity_step(snake, Dir6::South).is_err());
+        assert_eq!(world.terrain(snake_door), Terrain::Door);
+    }
+
+    #[test]
+    fn test_close_door() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        let door = loc + Dir6::North;
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(door, Terrain::OpenDoor);
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+
+        // Can't close a door with something in the doorway.
+        let snake = world.spawn(&Form::named("snake").unwrap().loadout, door);
+        assert!(world.entity_close_door(dreg, Dir6::North).is_err());
+        assert_eq!(world.terrain(door), Terrain::OpenDoor);
+        world.kill_entity(snake);
+
+        assert!(world.entity_close_door(dreg, Dir6::North).is_ok());
+        assert_eq!(world.terrain(door), Terrain::Door);
+        // Already closed.
+        assert!(world.entity_close_door(dreg, Dir6::North).is_err());
+    }
+
+    #[test]
+    fn test_locked_door() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        let door = loc + Dir6::North;
+        let beyond = door + Dir6::North;
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(door, Terrain::LockedDoor);
+        world.set_terrain(beyond, Terrain::Ground);
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+
+        assert!(world.entity_step(dreg, Dir6::North).is_err());
+        assert_eq!(world.terrain(door), Terrain::LockedDoor);
+        // Pathing doesn't try to go through the door either.
+        assert_eq!(world.pathing_dir_towards(dreg, beyond), None);
+
+        let key = world.spawn(&Form::named("key").unwrap().loadout, loc);
+        world.equip_item(key, dreg, Slot::InventoryJ);
+        assert_eq!(world.pathing_dir_towards(dreg, beyond), Some(Dir6::North));
+        assert!(world.entity_step(dreg, Dir6::North).is_ok());
+        assert_eq!(world.terrain(door), Terrain::OpenDoor);
+        // The key stays with its owner.
+        assert!(world.entity_contains(dreg, key));
+    }
+
+    #[test]
+    fn test_terrain_damage() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        let zap = Effect::Hit {
+            amount: 1,
+            damage: Damage::Electricity,
+        };
+        let burn = Effect::Hit {
+            amount: 1,
+            damage: Damage::Fire,
+        };
+
+        world.set_terrain(loc, Terrain::Rock);
+        world.apply_effect_to(&zap, loc, None);
+        assert_eq!(world.terrain(loc), Terrain::Rubble);
+        // Rubble doesn't burn.
+        world.apply_effect_to(&burn, loc, None);
+        assert_eq!(world.terrain(loc), Terrain::Rubble);
+
+        world.set_terrain(loc, Terrain::Door);
+        world.apply_effect_to(&burn, loc, None);
+        assert_eq!(world.terrain(loc), Terrain::Ash);
+
+        // Rock at the edge of the sector stays put so there's no digging out of the map.
+        let edge = Sector::new(1, 3, 4).origin();
+        assert!(edge.is_sector_edge());
+        world.set_terrain(edge, Terrain::Rock);
+        world.apply_effect_to(&zap, edge, None);
+        assert_eq!(world.terrain(edge), Terrain::Rock);
+    }
+
+    #[test]
+    fn test_set_void_terrain() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        world.set_terrain(loc, Terrain::Empty);
+        assert_eq!(world.terrain(loc), Terrain::Empty);
+    }
+
+    #[test]
+    fn test_deep_water() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        for &d in Dir6::iter() {
+            world.set_terrain(loc + d, Terrain::Ground);
+        }
+        world.set_terrain(loc, Terrain::Water);
+
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc + Dir6::South);
+        let snake = world.spawn(&Form::named("snake").unwrap().loadout, loc + Dir6::North);
+        let wraith = world.spawn(&Form::named("wraith").unwrap().loadout, loc + Dir6::Southeast);
+
+        assert!(!world.can_step(dreg, Dir6::North));
+        assert!(world.can_step(snake, Dir6::South));
+        assert!(world.can_step(wraith, Dir6::Northwest));
+
+        assert!(world.entity_step(snake, Dir6::South).is_ok());
+        assert!(world.is_swimming(snake));
+    }
+
+    #[test]
+    fn test_magma() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(loc + Dir6::North, Terrain::Magma);
+
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+        assert!(!world.can_step(dreg, Dir6::North));
+
+        // Knockback works even where the mob wouldn't go by itself, and the magma burns it up.
+        world.knock_back(dreg, Dir6::North);
+        assert!(!world.is_alive(dreg));
+
+        let salamander = world.spawn(&Form::named("salamander").unwrap().loadout, loc);
+        assert!(world.can_step(salamander, Dir6::North));
+    }
+
+    #[test]
+    fn test_open_sea() {
+        let world = World::new(1);
+        // Well outside the surface map.
+        let sea = Sector::new(5, 5, 0).center();
+        assert_eq!(world.terrain(sea), Terrain::Water);
+        assert!(!world.is_valid_location(sea));
+    }
+
+    #[test]
+    fn test_traps() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        let hidden = loc + Dir6::Southwest;
+        let pit = loc + Dir6::North;
+        for &cell in &[loc, hidden, pit] {
+            world.set_terrain(cell, Terrain::Ground);
+        }
+        let player = world.player().unwrap();
+        world.set_entity_location(player, loc);
+
+        // Hidden traps can be found by searching.
+        world.spawn(&Form::named("alarm trap").unwrap().loadout, hidden);
+        assert!(!world.player_knows_trap(hidden));
+        world.detect_hidden(player, 1);
+        assert!(world.player_knows_trap(hidden));
+
+        // Stepping on a trap sets it off and shows it.
+        world.spawn(&Form::named("pit trap").unwrap().loadout, pit);
+        let hp = world.hp(player);
+        assert!(world.entity_step(player, Dir6::North).is_ok());
+        assert_eq!(world.location(player), Some(pit));
+        assert!(world.hp(player) < hp);
+        assert!(world.player_knows_trap(pit));
+
+        // Flyers don't set traps off.
+        let wraith = world.spawn(&Form::named("wraith").unwrap().loadout, loc);
+        world.trigger_trap(wraith, pit);
+        assert_eq!(world.hp(wraith), world.max_hp(wraith));
+    }
+
+    #[test]
+    fn test_secret_door() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        let door = loc + Dir6::North;
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(door, Terrain::SecretDoor);
+        let player = world.player().unwrap();
+        world.set_entity_location(player, loc);
+
+        assert!(world.entity_step(player, Dir6::North).is_err());
+        world.detect_hidden(player, 1);
+        assert_eq!(world.terrain(door), Terrain::Door);
+        assert!(world.entity_step(player, Dir6::North).is_ok());
+    }
+
+    #[test]
+    fn test_notice() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        let player = world.player().unwrap();
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_entity_location(player, loc);
+
+        // Snakes are sharp-eyed enough to always spot the player right next to them.
+        world.set_terrain(loc + Dir6::North, Terrain::Ground);
+        let snake = world.spawn(&Form::named("snake").unwrap().loadout, loc + Dir6::North);
+        assert!(world.notices(snake, player));
+
+        // Can't notice anything through a wall.
+        let hidden = loc + Dir6::South + Dir6::South;
+        world.set_terrain(hidden, Terrain::Ground);
+        world.set_terrain(loc + Dir6::South, Terrain::Wall);
+        let snake = world.spawn(&Form::named("snake").unwrap().loadout, hidden);
+        assert!(!world.notices(snake, player));
+    }
+
+    #[test]
+    fn test_item_stacks() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        for &cell in &[loc, loc + Dir6::North, loc + Dir6::South] {
+            world.set_terrain(cell, Terrain::Ground);
+        }
+        let scroll = &Form::named("scroll of lightning").unwrap().loadout;
+
+        // Matching items dropped in the same spot merge, and spawning gives the stack.
+        let stack = world.spawn(scroll, loc);
+        assert_eq!(world.spawn(scroll, loc), stack);
+        assert_eq!(world.item_count(stack), 2);
+        assert_eq!(world.entities_at(loc), vec![stack]);
+
+        // Items that aren't used up don't stack.
+        let sword = world.spawn(&Form::named("sword").unwrap().loadout, loc + Dir6::South);
+        assert!(!world.can_stack(stack, sword));
+
+        let split = world.split_stack(stack, loc + Dir6::North).unwrap();
+        assert_ne!(split, stack);
+        assert_eq!(world.location(split), Some(loc + Dir6::North));
+        assert_eq!(world.item_count(stack), 1);
+        assert_eq!(world.item_count(split), 1);
+        assert!(world.can_stack(stack, split));
+        assert!(world.split_stack(stack, loc).is_none());
+
+        // Picked up items join the stack already carried.
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc + Dir6::South);
+        assert!(world.entity_take(dreg, split).is_ok());
+        assert!(world.entity_take(dreg, stack).is_ok());
+        assert!(!world.is_alive(stack));
+        assert_eq!(world.item_count(split), 2);
+        assert!(world.entity_contains(dreg, split));
+    }
+
+    #[test]
+    fn test_item_names() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(loc + Dir6::North, Terrain::Ground);
+        let player = world.player().unwrap();
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc + Dir6::North);
+        let wand = world.spawn(&Form::named("wand of fireball").unwrap().loadout, loc);
+
+        // The player only sees the disguise, others know what the item is.
+        assert_ne!(world.entity_name(wand), "wand of fireball");
+        assert_eq!(world.entity_name_for(player, wand), world.entity_name(wand));
+        assert_eq!(world.entity_name_for(dreg, wand), "wand of fireball");
+
+        world.identify_item(wand);
+        assert_eq!(world.entity_name(wand), "wand of fireball");
+    }
+
+    fn cursed_loadout(world: &mut World, name: &str) -> Loadout {
+        let form = Form::named(name).unwrap();
+        loop {
+            let loadout = form.roll_loadout(world.rng(), 0);
+            // Skip affixes so that the name and stats stay predictable.
+            if loadout.item.as_ref().map_or(false, |i| i.cursed && i.affixes.is_empty()) {
+                return loadout;
+            }
+        }
+    }
+
+    #[test]
+    fn test_cursed_item() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(loc + Dir6::North, Terrain::Ground);
+        let player = world.player().unwrap();
+        world.set_entity_location(player, loc);
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc + Dir6::North);
+
+        let sword = world.spawn(&cursed_loadout(&mut world, "sword"), loc);
+
+        // The bonus has turned into a penalty, but only others can tell at a glance.
+        assert_eq!(world.stats(sword).attack, -1);
+        assert_eq!(world.stats(sword).power, 10);
+        assert_eq!(world.entity_name(sword), "sword");
+        assert_eq!(world.entity_name_for(dreg, sword), "cursed sword");
+
+        // Wielding it reveals the curse and it can't be taken off.
+        world.equip_item(sword, player, Slot::InventoryJ);
+        assert!(world.equip(Slot::InventoryJ).is_ok());
+        assert_eq!(world.entity_equipped(player, Slot::InventoryJ), None);
+        assert_eq!(world.entity_name(sword), "cursed sword");
+        assert!(world.equip(Slot::Melee).is_err());
+        assert_eq!(world.entity_equipped(player, Slot::Melee), Some(sword));
+
+        world.uncurse(sword);
+        assert_eq!(world.entity_name(sword), "sword");
+        assert_eq!(world.stats(sword).attack, 10);
+        assert!(world.equip(Slot::Melee).is_ok());
+
+        // A cursed helmet still protects your head.
+        let helmet = world.spawn(&cursed_loadout(&mut world, "helmet"), loc);
+        assert_eq!(world.stats(helmet).armor, 1);
+    }
+
+    #[test]
+    fn test_instant_items() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        for &l in &[loc, loc + Dir6::North, loc + Dir6::Northeast] {
+            world.set_terrain(l, Terrain::Ground);
+        }
+        let orb = world.spawn(&Form::named("health orb").unwrap().loadout, loc + Dir6::North);
+
+        // Instant items can't be picked up.
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+        assert!(world.entity_take(dreg, orb).is_err());
+        assert_eq!(world.location(orb), Some(loc + Dir6::North));
+
+        // Non-scavengers walk over them.
+        let snake = world.spawn(&Form::named("snake").unwrap().loadout, loc + Dir6::Northeast);
+        assert!(world.entity_step(snake, Dir6::Northwest).is_ok());
+        assert!(world.is_alive(orb));
+        assert!(world.entity_step(snake, Dir6::Southeast).is_ok());
+
+        // Scavengers use them up and get the effect.
+        world.damage(dreg, 1, Damage::Physical, None);
+        assert_eq!(world.hp(dreg), world.max_hp(dreg) - 1);
+        assert!(world.entity_step(dreg, Dir6::North).is_ok());
+        assert!(!world.is_alive(orb));
+        assert_eq!(world.hp(dreg), world.max_hp(dreg));
+        assert_eq!(world.location(dreg), Some(loc + Dir6::North));
+    }
+
+    #[test]
+    fn test_containers() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        world.set_terrain(loc, Terrain::Ground);
+        world.set_terrain(loc + Dir6::North, Terrain::Ground);
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+        let chest = world.spawn(&Form::named("chest").unwrap().loadout, loc);
+        assert_eq!(world.reachable_container(dreg), Some(chest));
+        // Chests stay where they are.
+        assert!(world.entity_take(dreg, chest).is_err());
+        assert_eq!(world.location(chest), Some(loc));
+        assert!(!world.entity_contains(dreg, chest));
+
+        let scroll = Form::named("scroll of lightning").unwrap();
+        let stack = world.spawn(&scroll.loadout, loc);
+        world.spawn(&scroll.loadout, loc);
+        assert!(world.entity_take(dreg, stack).is_ok());
+        let slot = Slot::iter()
+            .cloned()
+            .find(|&slot| world.entity_equipped(dreg, slot) == Some(stack))
+            .unwrap();
+
+        assert!(world.entity_store(dreg, slot, chest).is_ok());
+        assert_eq!(world.entities_in(chest), vec![stack]);
+        assert_eq!(world.item_count(stack), 2);
+        assert_eq!(world.entity_equipped(dreg, slot), None);
+        // Items in a container are found at the container's location, not on the map.
+        assert_eq!(world.location(stack), Some(loc));
+        assert!(!world.entities_at(loc).contains(&stack));
+
+        // Taking out a stack joins it with the one already carried.
+        let carried = world.spawn(&scroll.loadout, loc + Dir6::North);
+        assert!(world.entity_take(dreg, carried).is_ok());
+        assert!(world.entity_take(dreg, stack).is_ok());
+        assert!(world.entities_in(chest).is_empty());
+        assert!(!world.is_alive(stack));
+        assert_eq!(world.item_count(carried), 3);
+        assert!(world.entity_contains(dreg, carried));
+
+        // A bag can't go inside itself.
+        let bag = world.spawn(&Form::named("bag").unwrap().loadout, loc + Dir6::North);
+        assert!(world.entity_take(dreg, bag).is_ok());
+        let slot = Slot::iter()
+            .cloned()
+            .find(|&slot| world.entity_equipped(dreg, slot) == Some(bag))
+            .unwrap();
+        assert!(world.entity_store(dreg, slot, bag).is_err());
+        assert_eq!(world.entity_equipped(dreg, slot), Some(bag));
+
+        // Dead mobs leave their belongings in a corpse.
+        world.damage(dreg, 100, Damage::Physical, None);
+        assert!(!world.is_alive(dreg));
+        let corpse = world
+            .entities_at(loc)
+            .into_iter()
+            .find(|&e| world.spawn_name(e) == Some("corpse"))
+            .unwrap();
+        let mut contents = world.entities_in(corpse);
+        contents.sort();
+        let mut expected = vec![carried, bag];
+        expected.sort();
+        assert_eq!(contents, expected);
+        assert_eq!(world.item_count(carried), 3);
+    }
+
+    #[test]
+    fn test_throw() {
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        for y in -6..2 {
+            for x in -3..3 {
+                world.set_terrain(Location::new(loc.x + x, loc.y + y, loc.z), Terrain::Ground);
+            }
+        }
+        let player = world.player().unwrap();
+        world.set_entity_location(player, loc);
+        let target = loc + Dir6::North + Dir6::North;
+        let ogre = world.spawn(&Form::named("ogre").unwrap().loadout, target);
+        let near = world.spawn(&Form::named("dreg").unwrap().loadout, target + Dir6::Northeast);
+        let far = world.spawn(
+            &Form::named("dreg").unwrap().loadout,
+            target + Dir6::North + Dir6::North,
+        );
+
+        // Things hit the first mob in their path and land there.
+        let torch = world.spawn(&Form::named("torch").unwrap().loadout, loc);
+        world.throw_item(torch, loc, Dir6::North, Some(player));
+        assert!(world.is_alive(torch));
+        assert_eq!(world.location(torch), Some(target));
+
+        let potion = Form::named("potion of confusion").unwrap();
+        let stack = world.spawn(&potion.loadout, loc);
+        world.spawn(&potion.loadout, loc);
+        assert_eq!(world.item_count(stack), 2);
+        assert!(world.entity_take(player, stack).is_ok());
+        let slot = Slot::iter()
+            .cloned()
+            .find(|&slot| world.entity_equipped(player, slot) == Some(stack))
+            .unwrap();
+
+        // Potions shatter and splash whatever is around where they hit.
+        assert!(world.throw(slot, Dir6::North).is_ok());
+        assert_eq!(world.item_count(stack), 1);
+        assert_eq!(world.entity_equipped(player, slot), Some(stack));
+        assert!(world.is_identified(stack));
+        assert!(world.has_status(ogre, Status::Confused));
+        assert!(world.has_status(near, Status::Confused));
+        assert!(!world.has_status(far, Status::Confused));
+        assert!(!world.has_status(player, Status::Confused));
+    }
+
+    #[test]
+    fn test_spells() {
+        use item::MagicEffect;
+
+        let mut world = World::new(1);
+        let loc = Sector::new(1, 3, 4).center();
+        world.set_terrain(loc, Terrain::Ground);
+        let player = world.player().unwrap();
+        world.set_entity_location(player, loc);
+
+        // Tomes go in the inventory, they can't be equipped in spell slots.
+        let tome = world.spawn(&Form::named("tome of healing").unwrap().loadout, loc);
+        assert!(world.entity_take(player, tome).is_ok());
+        assert_eq!(world.entity_equipped(player, Slot::InventoryJ), Some(tome));
+        assert!(world.equip(Slot::InventoryJ).is_err());
+        assert_eq!(world.known_spell(player, Slot::Spell1), None);
+        assert!(world.cast(Slot::Spell1).is_err());
+
+        // Reading the tome teaches the spell and uses up the tome.
+        assert!(world.use_item(Slot::InventoryJ).is_ok());
+        assert!(!world.is_alive(tome));
+        assert_eq!(world.known_spell(player, Slot::Spell1), Some(MagicEffect::Heal));
+
+        // A spell can't be learned twice.
+        let tome = world.spawn(&Form::named("tome of healing").unwrap().loadout, loc);
+        assert!(world.entity_take(player, tome).is_ok());
+        assert!(world.use_item(Slot::InventoryJ).is_err());
+        assert!(world.is_alive(tome));
+        assert_eq!(world.known_spell(player, Slot::Spell2), None);
+
+        let full = world.mana(player);
+        let cost = MagicEffect::Heal.mana_cost();
+        world.damage(player, 5, Damage::Physical, None);
+        let hp = world.hp(player);
+        assert!(world.cast(Slot::Spell1).is_ok());
+        assert_eq!(world.mana(player), full - cost);
+        assert!(world.hp(player) > hp);
+
+        // Failed casts don't cost anything.
+        assert!(world.cast_directed(Slot::Spell1, Dir6::North).is_err());
+        assert_eq!(world.mana(player), full - cost);
+        while world.mana(player) >= cost {
+            assert!(world.cast(Slot::Spell1).is_ok());
+        }
+        let left = world.mana(player);
+        assert!(world.cast(Slot::Spell1).is_err());
+        assert_eq!(world.mana(player), left);
+
+        // Mana comes back over time.
+        for _ in 0..10 {
+            let _ = world.pass();
+        }
+        assert!(world.mana(player) > left);
+
+        // Other casters heal just the same.
+        let dreg = world.spawn(&Form::named("dreg").unwrap().loadout, loc);
+        world.damage(dreg, 1, Damage::Physical, None);
+        world.cast_spell(loc, MagicEffect::Heal, None, Some(dreg));
+        assert_eq!(world.hp(dreg), world.max_hp(dreg));
+    }
+}
@@ -0,0 +1,366 @@
+use Prefab;
+use calx_alg::noise;
+use calx_grid::{Dijkstra, Dir6, HexGeom};
+use euclid::{Vector2D, vec2};
+use location::{Location, SECTOR_HEIGHT, SECTOR_WIDTH, Sector};
+use mapfile;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use terrain::Terrain;
+
+/// Radius of the surface map in sectors around the origin sector.
+///
+/// Everything outside the area is open sea.
+const RADIUS_IN_SECTORS: i16 = 1;
+
+/// Radius of the village prefabs.
+const VILLAGE_RADIUS: i32 = 6;
+
+/// Generated surface level.
+pub struct Overworld {
+    pub terrain: HashMap<Location, Terrain>,
+    /// Named entity spawns from prefabs.
+    pub spawns: Vec<(Location, String)>,
+    /// Sites for the tops of dungeon stairwells.
+    pub dungeon_entrances: Vec<Location>,
+}
+
+impl Overworld {
+    /// Generate the surface map with the given number of dungeon entrances.
+    pub fn new<R: Rng>(rng: &mut R, seed: u32, n_entrances: usize) -> Overworld {
+        let cells = area();
+
+        let elevation: HashMap<Location, f32> =
+            cells.iter().map(|&loc| (loc, elevation(seed, loc))).collect();
+
+        let mut ret = Overworld {
+            terrain: cells
+                .iter()
+                .map(|&loc| (loc, biome(seed, elevation[&loc], loc)))
+                .collect(),
+            spawns: Vec::new(),
+            dungeon_entrances: Vec::new(),
+        };
+
+        // Lakes are just the low spots of the elevation map, rivers run downhill into them from
+        // the highlands.
+        for _ in 0..rng.gen_range(2, 5) {
+            ret.dig_river(rng, &cells, &elevation);
+        }
+
+        let mut sites = Vec::new();
+
+        let n_villages = rng.gen_range(2, 4);
+        for i in 0..n_villages {
+            let loc = ret.find_site(rng, &cells, &sites, VILLAGE_RADIUS, 30);
+            let prefab = village(rng);
+            ret.stamp(loc - prefab_center(&prefab), &prefab);
+            if i == 0 {
+                // The player starts from the middle of the first village.
+                ret.spawns.push((loc, "player".to_string()));
+            }
+
+            if let Some(&prev) = sites.last() {
+                ret.road(prev, loc);
+            }
+            sites.push(loc);
+        }
+        let villages = sites.clone();
+
+        for _ in 0..n_entrances {
+            let loc = ret.find_site(rng, &cells, &sites, 3, 16);
+            // Clear the entrance cell and the approach to it, the stairwell itself gets built
+            // when the dungeon below is connected. Mark the stairwell site as wall for now so
+            // that roads won't get routed through it.
+            for v in hex_area(1) {
+                ret.terrain.insert(loc + vec2(1, 1) + v, Terrain::Wall);
+            }
+            ret.terrain.insert(loc, Terrain::Ground);
+            ret.terrain.insert(loc + Dir6::North, Terrain::Ground);
+
+            let nearest_village = *villages
+                .iter()
+                .min_by_key(|v| v.metric_distance(loc))
+                .expect("No villages");
+            ret.road(nearest_village, loc + Dir6::North);
+
+            ret.dungeon_entrances.push(loc);
+            sites.push(loc);
+        }
+
+        ret
+    }
+
+    /// Write a prefab on the map.
+    fn stamp(&mut self, origin: Location, prefab: &Prefab) {
+        for (p, &(ref terrain, ref spawns)) in prefab.iter() {
+            let loc = origin + p;
+            self.terrain.insert(loc, *terrain);
+            for spawn in spawns.iter() {
+                self.spawns.push((loc, spawn.clone()));
+            }
+        }
+    }
+
+    /// Find a random land location that is away from existing sites.
+    ///
+    /// The `radius` neighborhood of the location is guaranteed to be inside the surface area.
+    fn find_site<R: Rng>(
+        &self,
+        rng: &mut R,
+        cells: &[Location],
+        sites: &[Location],
+        radius: i32,
+        spacing: i32,
+    ) -> Location {
+        for attempt in 0..1000 {
+            // Start relaxing the spacing requirement if it's proving hard to satisfy.
+            let spacing = spacing - attempt / 100;
+
+            let loc = *rng.choose(cells).unwrap();
+            if self.terrain[&loc].is_water() {
+                continue;
+            }
+            if sites.iter().any(|s| s.metric_distance(loc) < spacing) {
+                continue;
+            }
+            if !hex_area(radius).into_iter().all(|v| {
+                self.terrain.contains_key(&(loc + v))
+            })
+            {
+                continue;
+            }
+
+            return loc;
+        }
+
+        // Give up and go with the middle of the map.
+        Sector::new(0, 0, 0).center()
+    }
+
+    /// Run a river downhill from a random highland spot until it reaches open water.
+    ///
+    /// If the river gets stuck in a hollow, it pools into a lake there.
+    fn dig_river<R: Rng>(
+        &mut self,
+        rng: &mut R,
+        cells: &[Location],
+        elevation: &HashMap<Location, f32>,
+    ) {
+        let highlands: Vec<Location> = cells
+            .iter()
+            .filter(|loc| elevation[loc] > 0.3)
+            .cloned()
+            .collect();
+        let mut loc = match rng.choose(&highlands) {
+            Some(&loc) => loc,
+            None => return,
+        };
+
+        let mut visited = HashSet::new();
+        loop {
+            self.terrain.insert(loc, Terrain::Water);
+            visited.insert(loc);
+
+            // Meander a bit instead of always taking the steepest slope.
+            let next = Dir6::iter()
+                .map(|&d| loc + d)
+                .filter(|n| elevation.contains_key(n) && !visited.contains(n))
+                .map(|n| (n, elevation[&n] + rng.gen_range(0.0, 0.1)))
+                .fold(None, |acc: Option<(Location, f32)>, (n, e)| match acc {
+                    Some((_, best)) if best <= e => acc,
+                    _ => Some((n, e)),
+                });
+
+            match next {
+                // Flowed off the edge of the map.
+                None => return,
+                Some((next, _)) => {
+                    if self.terrain[&next].is_water() {
+                        // Reached a lake or the sea.
+                        return;
+                    }
+                    if elevation[&next] > elevation[&loc] + 0.05 {
+                        // Stuck in a hollow, make a lake.
+                        for v in hex_area(2) {
+                            if self.terrain.contains_key(&(loc + v)) {
+                                self.terrain.insert(loc + v, Terrain::Water);
+                            }
+                        }
+                        return;
+                    }
+                    loc = next;
+                }
+            }
+        }
+    }
+
+    /// Connect two points with a road.
+    ///
+    /// Roads will go around water and mountains if they can, and ford or tunnel through them if
+    /// they can't.
+    fn road(&mut self, a: Location, b: Location) {
+        let path = self.find_path(a, b, |t| !t.is_water() && t != Terrain::Rock)
+            .or_else(|| self.find_path(a, b, |_| true));
+
+        for loc in path.unwrap_or_else(Vec::new) {
+            match self.terrain[&loc] {
+                Terrain::Tree | Terrain::Grass | Terrain::Rock => {
+                    self.terrain.insert(loc, Terrain::Ground);
+                }
+                Terrain::Water => {
+                    self.terrain.insert(loc, Terrain::ShallowWater);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn find_path<F: Fn(Terrain) -> bool>(
+        &self,
+        start: Location,
+        end: Location,
+        passable: F,
+    ) -> Option<Vec<Location>> {
+        let weights = Dijkstra::new(
+            vec![end],
+            |loc| match self.terrain.get(loc) {
+                // Never build roads through buildings.
+                Some(&Terrain::Wall) | Some(&Terrain::Door) | None => false,
+                Some(&t) => passable(t),
+            },
+            10_000,
+        ).weights;
+
+        if !weights.contains_key(&start) {
+            return None;
+        }
+
+        let mut ret = vec![start];
+        let mut loc = start;
+        while loc != end {
+            loc = Dir6::iter()
+                .map(|&d| loc + d)
+                .filter(|n| weights.contains_key(n))
+                .min_by_key(|n| weights[n])
+                .expect("Broken road distance map");
+            ret.push(loc);
+        }
+
+        Some(ret)
+    }
+}
+
+/// Return the sectors that make up the surface map.
+pub fn sectors() -> Vec<Sector> {
+    let mut ret = Vec::new();
+    for y in -RADIUS_IN_SECTORS..(RADIUS_IN_SECTORS + 1) {
+        for x in -RADIUS_IN_SECTORS..(RADIUS_IN_SECTORS + 1) {
+            ret.push(Sector::new(x, y, 0));
+        }
+    }
+    ret
+}
+
+/// Return whether the sector is part of the surface map.
+pub fn contains(sector: Sector) -> bool {
+    sector.z == 0 && -RADIUS_IN_SECTORS <= sector.x && sector.x <= RADIUS_IN_SECTORS &&
+        -RADIUS_IN_SECTORS <= sector.y && sector.y <= RADIUS_IN_SECTORS
+}
+
+/// Return all the cells in the surface area.
+fn area() -> Vec<Location> { sectors().into_iter().flat_map(|s| s.iter()).collect() }
+
+/// Return the offsets of the cells in a hexagon with the given radius.
+fn hex_area(radius: i32) -> Vec<Vector2D<i32>> {
+    let mut ret = Vec::new();
+    for y in -radius..(radius + 1) {
+        for x in -radius..(radius + 1) {
+            if vec2(x, y).hex_dist() <= radius {
+                ret.push(vec2(x, y));
+            }
+        }
+    }
+    ret
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 { a + (b - a) * t }
+
+fn smoothstep(t: f32) -> f32 { t * t * (3.0 - 2.0 * t) }
+
+/// Smoothly varying noise in [-1.0, 1.0].
+///
+/// Interpolates between random values on a lattice over the rectangular screen coordinates, scale
+/// is the lattice cell size.
+fn value_noise(seed: u32, scale: f32, loc: Location) -> f32 {
+    let (u, v) = loc.to_rect_coords();
+    let (u, v) = (u as f32 / scale, v as f32 / scale);
+    let (u0, v0) = (u.floor(), v.floor());
+    let (fu, fv) = (smoothstep(u - u0), smoothstep(v - v0));
+    let (i, j) = (u0 as i32, v0 as i32);
+
+    let lattice = |i: i32, j: i32| noise((seed as i32).wrapping_add(i * 59 + j * 919));
+
+    lerp(
+        lerp(lattice(i, j), lattice(i + 1, j), fu),
+        lerp(lattice(i, j + 1), lattice(i + 1, j + 1), fu),
+        fv,
+    )
+}
+
+/// Terrain elevation, sinks below sea level towards the edges of the surface area.
+fn elevation(seed: u32, loc: Location) -> f32 {
+    let e = 0.65 * value_noise(seed, 12.0, loc) +
+        0.35 * value_noise(seed.wrapping_add(1), 5.0, loc);
+
+    let (u, v) = loc.to_rect_coords();
+    let half_width = (SECTOR_WIDTH * (2 * RADIUS_IN_SECTORS as i32 + 1)) as f32 / 2.0;
+    let half_height = (SECTOR_HEIGHT * (2 * RADIUS_IN_SECTORS as i32 + 1)) as f32 / 2.0;
+    let du = (u as f32 - SECTOR_WIDTH as f32 / 2.0) / half_width;
+    let dv = (v as f32 - SECTOR_HEIGHT as f32 / 2.0) / half_height;
+    let edginess = du.abs().max(dv.abs());
+
+    e - 1.5 * edginess.powi(4)
+}
+
+fn biome(seed: u32, elevation: f32, loc: Location) -> Terrain {
+    use Terrain::*;
+
+    let moisture = value_noise(seed.wrapping_add(2), 8.0, loc);
+
+    match elevation {
+        e if e < -0.35 => Water,
+        e if e < -0.3 => ShallowWater,
+        // Beaches
+        e if e < -0.2 => Ground,
+        // Mountains
+        e if e > 0.55 => Rock,
+        // Forest, with some clearings
+        _ if moisture > 0.15 => if loc.noise() > -0.3 { Tree } else { Grass },
+        // Meadows, with the occasional lone tree
+        _ => if loc.noise() > 0.8 { Tree } else { Grass },
+    }
+}
+
+/// Village layouts in the map editor prefab format.
+const VILLAGES: [&'static str; 3] = [
+    include_str!("../prefabs/village1.ron"),
+    include_str!("../prefabs/village2.ron"),
+    include_str!("../prefabs/village3.ron"),
+];
+
+/// Load a random village prefab.
+fn village<R: Rng>(rng: &mut R) -> Prefab {
+    let text = rng.choose(&VILLAGES).unwrap();
+    mapfile::load_prefab(&mut text.as_bytes()).expect("Invalid village prefab")
+}
+
+/// Return the offset of the middle of a prefab.
+///
+/// Prefabs carry no positioning data, so this is used to center them on their sites.
+fn prefab_center(prefab: &Prefab) -> Vector2D<i32> {
+    let (sum, n) = prefab
+        .iter()
+        .fold((vec2(0, 0), 0), |(sum, n), (p, _)| (sum + p, n + 1));
+    vec2(sum.x / n, sum.y / n)
+}
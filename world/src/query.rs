@@ -1,19 +1,24 @@
 use FovStatus;
+use affix;
 use Prefab;
 use calx_ecs::Entity;
+use cloud::Cloud;
 use calx_grid::{Dir6, HexGeom};
 use components::{Alignment, BrainState, Icon, Status};
 use euclid::{Vector2D, vec2};
 use form;
-use item::{EquipType, ItemType, Slot};
+use identify::Identification;
+use effect::Effect;
+use item::{EquipType, ItemType, MagicEffect, Slot};
 use location::Location;
 use stats;
 use stats::Intrinsic;
+use std::cmp;
 use std::collections::{HashSet, VecDeque};
 use std::iter::FromIterator;
 use std::slice;
 use terraform::TerrainQuery;
-use terrain::Terrain;
+use terrain::{Kind, Terrain};
 use volume::Volume;
 use world::Ecs;
 
@@ -46,12 +51,21 @@ pub trait Query: TerrainQuery + Sized {
     /// Return entities at the given location.
     fn entities_at(&self, loc: Location) -> Vec<Entity>;
 
+    /// Return entities on the map near a location.
+    ///
+    /// Includes every entity within `radius` steps of center and possibly some more that are a
+    /// bit further away.
+    fn entities_around(&self, center: Location, radius: u32) -> Vec<Entity>;
+
     /// Return entities inside another entity.
     fn entities_in(&self, parent: Entity) -> Vec<Entity>;
 
     /// Return reference to the world entity component system.
     fn ecs(&self) -> &Ecs;
 
+    /// Return the item appearances and identified item kinds of the game.
+    fn identification(&self) -> &Identification;
+
     /// Return the item parent has equipped in slot.
     fn entity_equipped(&self, parent: Entity, slot: Slot) -> Option<Entity>;
 
@@ -59,6 +73,35 @@ pub trait Query: TerrainQuery + Sized {
 
     fn sphere_volume(&self, origin: Location, radius: u32) -> Volume;
 
+    /// Return the cloud at location, if any.
+    fn cloud(&self, loc: Location) -> Option<Cloud>;
+
+    /// Return the cells that can be seen from origin within range.
+    fn fov_from(&self, origin: Location, range: u32) -> HashSet<Location>;
+
+    /// Return how far the entity can see.
+    fn sight_range(&self, e: Entity) -> u32 {
+        const DEFAULT_SIGHT_RANGE: i32 = 12;
+        cmp::max(0, DEFAULT_SIGHT_RANGE + self.stats(e).perception) as u32
+    }
+
+    /// Return whether the entity can see the location with its own eyes.
+    fn can_see(&self, e: Entity, loc: Location) -> bool {
+        let range = self.sight_range(e);
+        match self.location(e) {
+            // Cheap distance check first, FOV is expensive.
+            Some(origin) if origin.metric_distance(loc) <= range as i32 => {
+                self.fov_from(origin, range).contains(&loc)
+            }
+            _ => false,
+        }
+    }
+
+    /// Return the light level at location as seen by the player.
+    ///
+    /// Zero is complete darkness, `DAYLIGHT` is full daylight.
+    fn light_level(&self, loc: Location) -> u32;
+
     /// Return the AI state of an entity.
     fn brain_state(&self, e: Entity) -> Option<BrainState> {
         self.ecs().brain.get(e).map_or(
@@ -72,6 +115,13 @@ pub trait Query: TerrainQuery + Sized {
 
     fn is_item(&self, e: Entity) -> bool { self.ecs().item.contains(e) }
 
+    /// Return the mana an entity has left for casting spells.
+    fn mana(&self, e: Entity) -> i32 {
+        self.max_mana(e) - self.ecs().health.get(e).map_or(0, |h| h.spent_mana)
+    }
+
+    fn max_mana(&self, e: Entity) -> i32 { self.stats(e).mana }
+
     /// Return the value for how a mob will react to other mobs.
     fn alignment(&self, e: Entity) -> Option<Alignment> {
         self.ecs().brain.get(e).map(|b| b.alignment)
@@ -107,11 +157,51 @@ pub trait Query: TerrainQuery + Sized {
     /// Return visual brush for an entity.
     fn entity_icon(&self, e: Entity) -> Option<Icon> { self.ecs().desc.get(e).map(|x| x.icon) }
 
-    fn entity_name(&self, e: Entity) -> String {
-        self.ecs().desc.get(e).map_or_else(
+    /// Return the name of an entity as the player knows it.
+    ///
+    /// Items the player hasn't identified are called by their appearance.
+    fn entity_name(&self, e: Entity) -> String { self.known_entity_name(e, true) }
+
+    /// Return the name of an entity as the observer knows it.
+    ///
+    /// Only the player needs to identify items, everyone else knows what they are.
+    fn entity_name_for(&self, observer: Entity, e: Entity) -> String {
+        self.known_entity_name(e, self.is_player(observer))
+    }
+
+    /// Return the name of an entity, disguising unidentified items if seen by the player.
+    ///
+    /// Item affixes are included in the name. The player only sees that an item is cursed after
+    /// finding out the hard way.
+    fn known_entity_name(&self, e: Entity, by_player: bool) -> String {
+        let name = self.ecs().desc.get(e).map_or_else(
             || "N/A".to_string(),
-            |x| x.name.clone(),
-        )
+            |x| match self.identification().appearance(&x.name) {
+                Some(appearance) if by_player => appearance.to_string(),
+                _ => x.name.clone(),
+            },
+        );
+
+        match self.ecs().item.get(e) {
+            Some(item) => {
+                let name = if item.affixes.is_empty() {
+                    name
+                } else {
+                    affix::item_name(&name, &item.affixes)
+                };
+                if item.cursed && (item.curse_known || !by_player) {
+                    format!("cursed {}", name)
+                } else {
+                    name
+                }
+            }
+            _ => name,
+        }
+    }
+
+    /// Return whether the player knows what kind of item this is.
+    fn is_identified(&self, item: Entity) -> bool {
+        self.spawn_name(item).map_or(true, |name| self.identification().is_known(name))
     }
 
     /// Return the (composite) stats for an entity.
@@ -140,14 +230,45 @@ pub trait Query: TerrainQuery + Sized {
         })
     }
 
+    /// Return a key item carried by the entity.
+    ///
+    /// Keys aren't tied to specific doors and aren't used up, one key opens every locked door.
+    fn find_key(&self, e: Entity) -> Option<Entity> {
+        Slot::iter()
+            .filter_map(|&slot| self.entity_equipped(e, slot))
+            .find(|&item| self.item_type(item) == Some(ItemType::Key))
+    }
+
+    /// Return whether the entity can close a door at location.
+    fn can_close_door(&self, e: Entity, loc: Location) -> bool {
+        self.terrain(loc) == Terrain::OpenDoor && self.has_intrinsic(e, Intrinsic::Hands) &&
+            self.entities_at(loc).is_empty()
+    }
+
     /// Return whether location blocks line of sight.
-    fn blocks_sight(&self, loc: Location) -> bool { self.terrain(loc).blocks_sight() }
+    fn blocks_sight(&self, loc: Location) -> bool {
+        self.terrain(loc).blocks_sight() || self.cloud(loc).map_or(false, |c| c.blocks_sight())
+    }
 
     /// Return whether the entity can occupy a location.
+    ///
+    /// Closed doors count as enterable if the entity is able to open them.
     fn can_enter(&self, e: Entity, loc: Location) -> bool {
-        if self.terrain(loc).is_door() && !self.has_intrinsic(e, Intrinsic::Hands) {
-            // Can't open doors without hands.
-            return false;
+        let terrain = self.terrain(loc);
+        if terrain.is_door() {
+            if !self.has_intrinsic(e, Intrinsic::Hands) {
+                // Can't open doors without hands.
+                return false;
+            }
+            if terrain == Terrain::LockedDoor && self.find_key(e).is_none() {
+                return false;
+            }
+        }
+        if terrain.is_deep_liquid() {
+            if !self.can_cross_liquid(e, terrain) {
+                return false;
+            }
+            return self.is_valid_location(loc) && !self.has_blocking_entity(loc);
         }
         if self.blocks_walk(loc) {
             return false;
@@ -155,6 +276,40 @@ pub trait Query: TerrainQuery + Sized {
         true
     }
 
+    /// Return whether the entity can move through deep water or magma.
+    fn can_cross_liquid(&self, e: Entity, terrain: Terrain) -> bool {
+        let flying = self.has_intrinsic(e, Intrinsic::Flying);
+        match terrain.kind() {
+            Kind::Water => flying || self.has_intrinsic(e, Intrinsic::Swimming),
+            Kind::Magma => flying || self.has_intrinsic(e, Intrinsic::FireImmune),
+            _ => true,
+        }
+    }
+
+    /// Return whether the entity is swimming in deep water.
+    fn is_swimming(&self, e: Entity) -> bool {
+        if self.has_intrinsic(e, Intrinsic::Flying) {
+            return false;
+        }
+        self.location(e).map_or(false, |loc| self.terrain(loc).kind() == Kind::Water)
+    }
+
+    /// Return whether terrain at the entity's location slows it down.
+    fn is_slowed_by_terrain(&self, e: Entity) -> bool {
+        if self.has_intrinsic(e, Intrinsic::Flying) {
+            return false;
+        }
+        self.location(e).map_or(false, |loc| self.terrain(loc).is_water())
+    }
+
+    /// Return whether the item is too heavy to carry while swimming.
+    fn is_heavy(&self, item: Entity) -> bool {
+        match self.item_type(item) {
+            Some(ItemType::Armor) | Some(ItemType::MeleeWeapon) => true,
+            _ => false,
+        }
+    }
+
     fn can_drop_item_at(&self, loc: Location) -> bool {
         if !self.is_valid_location(loc) {
             return false;
@@ -179,13 +334,14 @@ pub trait Query: TerrainQuery + Sized {
         if self.terrain(loc).blocks_walk() {
             return true;
         }
-        if self.entities_at(loc).into_iter().any(|e| {
-            self.is_blocking_entity(e)
-        })
-        {
-            return true;
-        }
-        false
+        self.has_blocking_entity(loc)
+    }
+
+    /// Return whether there are entities at location that block movement.
+    fn has_blocking_entity(&self, loc: Location) -> bool {
+        self.entities_at(loc).into_iter().any(
+            |e| self.is_blocking_entity(e),
+        )
     }
 
     /// Return whether a location contains mobs.
@@ -230,7 +386,7 @@ pub trait Query: TerrainQuery + Sized {
             1 => self.has_status(e, Status::Fast),
             2 => true,
             3 => self.has_intrinsic(e, Intrinsic::Quick),
-            4 => !self.has_intrinsic(e, Intrinsic::Slow),
+            4 => !self.has_intrinsic(e, Intrinsic::Slow) && !self.is_slowed_by_terrain(e),
             _ => panic!("Invalid action phase"),
         }
     }
@@ -487,6 +643,20 @@ pub trait Query: TerrainQuery + Sized {
     /// Return whether the player can currently directly see the given location.
     fn player_sees(&self, loc: Location) -> bool { self.fov_status(loc) == Some(FovStatus::Seen) }
 
+    fn is_trap(&self, e: Entity) -> bool { self.ecs().trap.contains(e) }
+
+    /// Return trap entity (if any) at given location.
+    fn trap_at(&self, loc: Location) -> Option<Entity> {
+        self.entities_at(loc).into_iter().find(|&e| self.is_trap(e))
+    }
+
+    /// Return whether the player has found the trap at location.
+    fn player_knows_trap(&self, loc: Location) -> bool {
+        self.player()
+            .and_then(|p| self.ecs().map_memory.get(p))
+            .map_or(false, |memory| memory.traps.contains(&loc))
+    }
+
     /// Return the set of mobs that are in update range.
     ///
     /// In a large game world, the active set is limited to the player's surroundings.
@@ -500,17 +670,149 @@ pub trait Query: TerrainQuery + Sized {
     /// Return number of times item can be used.
     fn uses_left(&self, item: Entity) -> u32 { self.ecs().item.get(item).map_or(0, |i| i.charges) }
 
+    /// Return the effect of an item that gets used up when stepped on.
+    fn instant_effect(&self, item: Entity) -> Option<Effect> {
+        match self.item_type(item) {
+            Some(ItemType::Instant(effect)) => effect.mob_effect(),
+            _ => None,
+        }
+    }
+
+    /// Return the magic effect of an item that is used without aiming.
+    fn undirected_effect(&self, item: Entity) -> Option<MagicEffect> {
+        match self.item_type(item) {
+            Some(ItemType::UntargetedUsable(effect)) => Some(effect),
+            _ => None,
+        }
+    }
+
+    /// Return the magic effect of an item that is zapped in a direction.
+    fn directed_effect(&self, item: Entity) -> Option<MagicEffect> {
+        match self.item_type(item) {
+            Some(ItemType::TargetedUsable(effect)) => Some(effect),
+            _ => None,
+        }
+    }
+
+    /// Return the spell a tome teaches when read.
+    fn spell_effect(&self, tome: Entity) -> Option<MagicEffect> {
+        match self.item_type(tome) {
+            Some(ItemType::Spell(effect)) => Some(effect),
+            _ => None,
+        }
+    }
+
+    /// Return the spell the mob has learned in a spell slot.
+    fn known_spell(&self, e: Entity, slot: Slot) -> Option<MagicEffect> {
+        slot.spell_index().and_then(|i| {
+            self.ecs().spellbook.get(e).and_then(|b| b.spells.get(i).cloned())
+        })
+    }
+
+    /// Return the effect of a potion on whoever drinks it or gets splashed with it.
+    fn potion_effect(&self, item: Entity) -> Option<Effect> {
+        match self.item_type(item) {
+            Some(ItemType::Potion(effect)) => effect.mob_effect(),
+            _ => None,
+        }
+    }
+
+    /// Return whether the mob uses up instant items it steps on.
+    fn is_scavenger(&self, e: Entity) -> bool {
+        self.ecs().brain.get(e).map_or(false, |b| b.scavenger)
+    }
+
+    fn is_container(&self, e: Entity) -> bool {
+        match self.item_type(e) {
+            Some(ItemType::Container { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Return whether the item can be picked up.
+    fn is_portable(&self, item: Entity) -> bool {
+        match self.item_type(item) {
+            Some(ItemType::Container { portable }) => portable,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn container_at(&self, loc: Location) -> Option<Entity> {
+        self.entities_at(loc).into_iter().find(|&e| self.is_container(e))
+    }
+
+    /// Return the container a mob can reach into.
+    ///
+    /// A container on the ground where the mob stands is preferred over one the mob carries.
+    fn reachable_container(&self, e: Entity) -> Option<Entity> {
+        if let Some(container) = self.location(e).and_then(|loc| self.container_at(loc)) {
+            return Some(container);
+        }
+
+        Slot::iter()
+            .filter_map(|&slot| self.entity_equipped(e, slot))
+            .find(|&item| self.is_container(item))
+    }
+
+    /// Return the items inside a container.
+    fn container_contents(&self, container: Entity) -> Vec<Entity> {
+        if self.is_container(container) {
+            self.entities_in(container)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Return whether the item is cursed.
+    fn is_cursed(&self, item: Entity) -> bool {
+        self.ecs().item.get(item).map_or(false, |i| i.cursed)
+    }
+
+    /// Return whether the item is equipped and can't be removed because of a curse.
+    fn is_stuck(&self, e: Entity, slot: Slot) -> bool {
+        slot.is_equipment_slot() &&
+            self.entity_equipped(e, slot).map_or(false, |item| self.is_cursed(item))
+    }
+
+    /// Return how many items there are in an item stack.
+    fn item_count(&self, item: Entity) -> u32 { self.ecs().item.get(item).map_or(0, |i| i.count) }
+
+    /// Return whether the item can form stacks with other items.
+    ///
+    /// Consumables stack, equipment and wands are tracked individually.
+    fn is_stackable(&self, item: Entity) -> bool { self.destroy_after_use(item) }
+
+    /// Return whether the two items can be merged into one stack.
+    fn can_stack(&self, a: Entity, b: Entity) -> bool {
+        if a == b || !self.is_stackable(a) || !self.is_stackable(b) {
+            return false;
+        }
+
+        match (self.ecs().item.get(a), self.ecs().item.get(b)) {
+            (Some(x), Some(y)) => {
+                x.item_type == y.item_type && x.charges == y.charges &&
+                    self.spawn_name(a) == self.spawn_name(b)
+            }
+            _ => false,
+        }
+    }
+
+    /// Return an item carried by the entity that the given item can be merged into.
+    fn carried_stack_for(&self, e: Entity, item: Entity) -> Option<Entity> {
+        Slot::iter()
+            .filter_map(|&slot| self.entity_equipped(e, slot))
+            .find(|&x| self.can_stack(x, item))
+    }
+
     fn destroy_after_use(&self, item: Entity) -> bool {
         // XXX: Fragile. What we want here is to tag potions and scrolls as destroyed when used and
-        // wands to stick around. Current item data doesn't have is_potion or is_scroll, but
-        // coincidentally the scrolls tend to be untargeted and the wands tend to be targeted
-        // spells, so we'll just use that as proxy.
-        self.ecs().item.get(item).map_or(false, |i| {
-            if let ItemType::UntargetedUsable(_) = i.item_type {
-                true
-            } else {
-                false
-            }
+        // wands to stick around. Current item data doesn't have is_scroll, but coincidentally the
+        // scrolls tend to be untargeted and the wands tend to be targeted spells, so we'll just
+        // use that as proxy.
+        self.ecs().item.get(item).map_or(false, |i| match i.item_type {
+            ItemType::UntargetedUsable(_) | ItemType::Potion(_) | ItemType::Spell(_) => true,
+            _ => false,
         })
     }
 
@@ -522,7 +824,6 @@ pub trait Query: TerrainQuery + Sized {
             Some(Helmet) => Some(EquipType::Head),
             Some(Armor) => Some(EquipType::Body),
             Some(Boots) => Some(EquipType::Feet),
-            Some(Spell) => Some(EquipType::Spell),
             Some(Trinket) => Some(EquipType::Trinket),
             _ => None,
         }
@@ -4,6 +4,7 @@ use item::Slot;
 use location::Location;
 use serde;
 use std::collections::BTreeMap;
+use std::collections::Bound::Included;
 
 /// Entities can be placed either on open locations or inside other entities.
 /// A sum type will represent this nicely.
@@ -136,6 +137,33 @@ impl Spatial {
     /// List entities at a location.
     pub fn entities_at(&self, loc: Location) -> Vec<Entity> { self.entities(At(loc)) }
 
+    /// List entities on the map within `radius` steps of center on the same z-level.
+    ///
+    /// Looks up the bounding box of the area from the index instead of going through every
+    /// entity, the results may include entities in the corners of the box that are further away.
+    pub fn entities_around(&self, center: Location, radius: u32) -> Vec<Entity> {
+        use std::cmp::{max, min};
+        let clamp = |x: i32| max(min(x, i16::max_value() as i32), i16::min_value() as i32);
+        let radius = radius as i32;
+        let (x0, x1) = (clamp(center.x as i32 - radius), clamp(center.x as i32 + radius));
+        let (y0, y1) = (clamp(center.y as i32 - radius), clamp(center.y as i32 + radius));
+
+        let mut ret = Vec::new();
+        for x in x0..(x1 + 1) {
+            // Locations sort by x first and y second, so each column of the box is one range.
+            let start = At(Location::new(x as i16, y0 as i16, i16::min_value()));
+            let end = At(Location::new(x as i16, y1 as i16, i16::max_value()));
+            for (p, es) in self.place_to_entities.range((Included(start), Included(end))) {
+                if let At(loc) = *p {
+                    if loc.z == center.z {
+                        ret.extend(es.iter().cloned());
+                    }
+                }
+            }
+        }
+        ret
+    }
+
     /// List entities in a container.
     pub fn entities_in(&self, parent: Entity) -> Vec<Entity> {
         self.place_to_entities.range(In(parent, None)..)
@@ -235,6 +263,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_entities_around() {
+        let mut ecs = Ecs::new();
+        let near = ecs.make();
+        let far = ecs.make();
+        let below = ecs.make();
+
+        let mut spatial = Spatial::new();
+        let center = Location::new(10, 10, 1);
+        spatial.insert_at(near, Location::new(8, 12, 1));
+        spatial.insert_at(far, Location::new(10, 14, 1));
+        spatial.insert_at(below, Location::new(10, 10, 0));
+
+        assert_eq!(spatial.entities_around(center, 2), vec![near]);
+        assert_eq!(spatial.entities_around(center, 4).len(), 2);
+    }
+
     #[test]
     fn test_serialization() {
         use ron::de;
@@ -256,4 +301,39 @@ mod test {
         assert_eq!(spatial2.get(e1), Some(p1));
         assert_eq!(spatial2.get(e2), Some(p2));
     }
+
+    #[test]
+    fn test_nested_containers() {
+        use ron::de;
+        use ron::ser;
+
+        let mut ecs = Ecs::new();
+        let chest = ecs.make();
+        let bag = ecs.make();
+        let item1 = ecs.make();
+        let item2 = ecs.make();
+
+        // Insert contents before their containers to check that order doesn't matter.
+        let mut spatial = Spatial::new();
+        spatial.insert(item1, Place::In(bag, None));
+        spatial.insert(item2, Place::In(chest, None));
+        spatial.insert(bag, Place::In(chest, None));
+        spatial.insert_at(chest, Location::new(10, 10, 0));
+
+        let saved = ser::to_string(&spatial).expect("Spatial serialization failed");
+        let mut spatial2: Spatial = de::from_str(&saved).expect("Spatial deserialization failed");
+
+        let mut contents = spatial2.entities_in(chest);
+        contents.sort();
+        let mut expected = vec![bag, item2];
+        expected.sort();
+        assert_eq!(contents, expected);
+        assert_eq!(spatial2.entities_in(bag), vec![item1]);
+        assert!(spatial2.contains(chest, item1));
+
+        // Removing the chest takes the contents with it.
+        spatial2.remove(chest);
+        assert_eq!(spatial2.get(bag), None);
+        assert_eq!(spatial2.get(item1), None);
+    }
 }
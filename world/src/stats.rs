@@ -21,6 +21,12 @@ pub struct Stats {
     pub ranged_range: u32,
     /// Ranged attack power
     pub ranged_power: i32,
+    /// Radius of light given off, zero for no light.
+    pub light: i32,
+    /// Bonus to sight range and noticing sneaking enemies.
+    pub perception: i32,
+    /// Makes sleeping enemies less likely to notice you.
+    pub stealth: i32,
 
     /// Bit flags for intrinsics
     pub intrinsics: u32,
@@ -53,6 +59,30 @@ impl Stats {
             ..self
         }
     }
+    pub fn light(self, light: i32) -> Stats { Stats { light, ..self } }
+    pub fn perception(self, perception: i32) -> Stats {
+        Stats {
+            perception,
+            ..self
+        }
+    }
+    pub fn stealth(self, stealth: i32) -> Stats { Stats { stealth, ..self } }
+
+    /// Return the stats of a cursed version of an item.
+    ///
+    /// Bonuses to attack, defense, perception and stealth turn into a -1 penalty. Armor is left
+    /// as it is, a cursed helmet still protects your head.
+    pub fn cursed(self) -> Stats {
+        fn malus(bonus: i32) -> i32 { if bonus > 0 { -1 } else { bonus } }
+
+        Stats {
+            attack: malus(self.attack),
+            defense: malus(self.defense),
+            perception: malus(self.perception),
+            stealth: malus(self.stealth),
+            ..self
+        }
+    }
 }
 
 impl Add<Stats> for Stats {
@@ -71,6 +101,9 @@ impl Add<Stats> for Stats {
             // type dealie.
             ranged_range: self.ranged_range + other.ranged_range,
             ranged_power: self.ranged_power + other.ranged_power,
+            light: self.light + other.light,
+            perception: self.perception + other.perception,
+            stealth: self.stealth + other.stealth,
             intrinsics: self.intrinsics | other.intrinsics,
         }
     }
@@ -87,4 +120,10 @@ pub enum Intrinsic {
     Hands,
     /// Explodes on death
     Deathsplosion,
+    /// Can swim in deep water.
+    Swimming,
+    /// Moves over water and magma unhindered.
+    Flying,
+    /// Does not take fire damage, can wade through magma.
+    FireImmune,
 }
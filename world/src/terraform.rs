@@ -20,11 +20,7 @@ pub trait TerrainQuery {
     ///
     /// The difference between the two is important in how map memory works, map memory display
     /// goes through border portals normally, but ignores hole portals.
-    fn is_border_portal(&self, _loc: Location) -> bool {
-        // TODO: Implement internal data for border portals
-        // Turn type Portal in the internal portal data into enum { Border(Portal), Edge(Portal) }
-        false
-    }
+    fn is_border_portal(&self, loc: Location) -> bool;
 
     /// The cell has not (probably) been touched by map generation yet.
     fn is_untouched(&self, loc: Location) -> bool;
@@ -58,7 +54,7 @@ pub trait Terraform {
     /// A zero value will generally reset the terrain to the map default.
     fn set_terrain(&mut self, loc: Location, terrain: terrain::Terrain);
 
-    /// Set a portal on map.
+    /// Set a hole portal on map.
     ///
     /// If the portal points to a location with an existing portal, the portal value will be
     /// modified to point to that portal's destination.
@@ -66,6 +62,11 @@ pub trait Terraform {
     /// If the portal does not involve any translation, it will not be added.
     fn set_portal(&mut self, loc: Location, portal: Portal);
 
+    /// Set a border portal on map.
+    ///
+    /// Works like `set_portal`, but the portal will be treated as a border portal.
+    fn set_border_portal(&mut self, loc: Location, portal: Portal);
+
     /// Remove any portals from given location.
     fn remove_portal(&mut self, loc: Location);
 }
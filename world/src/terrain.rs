@@ -1,3 +1,4 @@
+use effect::Damage;
 use std::slice;
 
 /// Movement effect of a terrain tile.
@@ -16,12 +17,16 @@ pub enum Kind {
     Window,
     /// A tile that blocks sight but can be walked through.
     Door,
-    /// Bodies of water, regular units can't walk into them.
+    /// Deep water, regular units can't walk into it.
     ///
-    /// Flying units (if we have any) can cross. Falling into water is going to involve tricky
-    /// logic since our maps aren't 3D.
+    /// Swimming units can cross, but are slowed down and must drop heavy gear to stay afloat.
+    /// Flying units can cross freely.
     Water,
+    /// Shallow water that can be waded across slowly.
+    Shallows,
     /// Like water, but much more fun.
+    ///
+    /// Only flying and fire-immune units can enter, anything else that ends up in it burns.
     Magma,
 }
 
@@ -90,11 +95,18 @@ terrain_enum! {
     Door:        TerrainData { name: "door",      kind: Kind::Door,   form: Form::Wall,  map_chars: "|",   is_irregular: false },
     // TODO: Get rid of corridor, it only makes sense for mapgen bookkeeping and that doesn't
     // belong in persistent map.
-    Corridor:    TerrainData { name: "ground",    kind: Kind::Ground, form: Form::Floor, map_chars: "_.,", is_irregular: true },
+    Corridor:    TerrainData { name: "ground",    kind: Kind::Corridor, form: Form::Floor, map_chars: "_.,", is_irregular: true },
     OpenDoor:    TerrainData { name: "open door", kind: Kind::Ground, form: Form::Wall,  map_chars: "",    is_irregular: true },
     // TODO: Get rid of grass2, give render a coherent noise source for tiles and make it do the
     // variation locally.
     Grass2:      TerrainData { name: "grass",     kind: Kind::Ground, form: Form::Floor, map_chars: "",    is_irregular: true },
+    LockedDoor:  TerrainData { name: "locked door", kind: Kind::Door, form: Form::Wall,  map_chars: "+",   is_irregular: false },
+    Ash:         TerrainData { name: "ash",       kind: Kind::Ground, form: Form::Floor, map_chars: "",    is_irregular: false },
+    Ice:         TerrainData { name: "ice",       kind: Kind::Ground, form: Form::Floor, map_chars: "",    is_irregular: false },
+    Rubble:      TerrainData { name: "rubble",    kind: Kind::Ground, form: Form::Prop,  map_chars: "",    is_irregular: false },
+    ShallowWater: TerrainData { name: "shallow water", kind: Kind::Shallows, form: Form::Floor, map_chars: "-", is_irregular: false },
+    // Looks like a wall until found.
+    SecretDoor:  TerrainData { name: "wall",      kind: Kind::Block,  form: Form::Wall,  map_chars: "",    is_irregular: false },
 }
 
 impl Terrain {
@@ -122,7 +134,7 @@ impl Terrain {
 
     pub fn blocks_walk(self) -> bool {
         match self.kind() {
-            Kind::Ground | Kind::Corridor | Kind::Door => false,
+            Kind::Ground | Kind::Corridor | Kind::Door | Kind::Shallows => false,
             _ => true,
         }
     }
@@ -131,10 +143,48 @@ impl Terrain {
 
     pub fn is_open(self) -> bool { self.kind() == Kind::Ground || self.kind() == Kind::Corridor }
 
+    /// Return whether the terrain is a closed door.
+    ///
+    /// Closed doors are opened by walking into them.
     pub fn is_door(self) -> bool { self.kind() == Kind::Door }
 
     pub fn is_luminous(self) -> bool { self.kind() == Kind::Magma }
 
+    /// Return whether the terrain is deep or shallow water.
+    pub fn is_water(self) -> bool {
+        match self.kind() {
+            Kind::Water | Kind::Shallows => true,
+            _ => false,
+        }
+    }
+
+    /// Return whether the terrain is a liquid that blocks walking but can be moved into by other
+    /// means.
+    pub fn is_deep_liquid(self) -> bool {
+        match self.kind() {
+            Kind::Water | Kind::Magma => true,
+            _ => false,
+        }
+    }
+
+    /// Return what the terrain turns into when hit by a type of damage.
+    ///
+    /// Returns `None` if the terrain is not affected.
+    pub fn damaged(self, damage: Damage) -> Option<Terrain> {
+        use self::Terrain::*;
+        match (damage, self) {
+            (Damage::Fire, Tree) |
+            (Damage::Fire, Grass) |
+            (Damage::Fire, Grass2) |
+            (Damage::Fire, Door) |
+            (Damage::Fire, OpenDoor) |
+            (Damage::Fire, LockedDoor) => Some(Ash),
+            (Damage::Cold, Water) => Some(Ice),
+            (Damage::Electricity, Rock) => Some(Rubble),
+            _ => None,
+        }
+    }
+
     pub fn is_wall(self) -> bool { self.form() == Form::Wall }
 
     pub fn is_hull(self) -> bool { self.form() == Form::Wall || self.form() == Form::Blob }
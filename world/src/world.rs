@@ -1,29 +1,37 @@
 use Rng;
 use calx_ecs::Entity;
-use calx_grid::HexFov;
+use calx_grid::{Dir6, HexGeom};
+use cloud::{self, Cloud};
 use command::{Command, CommandResult};
-use components;
+use components::{self, Appearance};
+use effect::Damage;
 use errors::*;
 use event::Event;
+use field::Field;
 use flags::Flags;
-use fov::SightFov;
+use fov::{FovCache, FovCells};
+use identify::Identification;
 use item::Slot;
+use light::LightMap;
 use location::{Location, Portal};
+use location_set::LocationSet;
 use mutate::Mutate;
+use overworld;
 use query::Query;
 use rand::SeedableRng;
 use ron;
 use spatial::{Place, Spatial};
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::io::{Read, Write};
 use std::iter::FromIterator;
 use std::slice;
 use terraform::{Terraform, TerrainQuery};
-use terrain::Terrain;
+use terrain::{self, Terrain};
 use volume::Volume;
-use worldgen::Worldgen;
+use worldgen::{MapPortal, Worldgen};
 
-pub const GAME_VERSION: &'static str = "0.1.0";
+pub const GAME_VERSION: &'static str = "0.2.0";
 
 Ecs! {
     desc: components::Desc,
@@ -33,6 +41,8 @@ Ecs! {
     item: components::Item,
     stats: components::StatsComponent,
     status: components::Statuses,
+    trap: components::Trap,
+    spellbook: components::Spellbook,
 }
 
 /// Toplevel game state object.
@@ -45,14 +55,29 @@ pub struct World {
     ecs: Ecs,
     /// Static startup game world
     worldgen: Worldgen,
+    /// Changes to the generated terrain.
+    terrain: Field<Option<Terrain>>,
+    /// Fire, smoke and gas clouds.
+    clouds: Field<Option<Cloud>>,
     /// Spatial index for game entities.
     spatial: Spatial,
     /// Global gamestate flags.
     flags: Flags,
+    /// Item appearances and the item kinds the player knows.
+    identification: Identification,
     /// Persistent random number generator.
     rng: Rng,
     /// Event queue
     events: Vec<Event>,
+    /// Light levels around the player, recomputed with the player's FOV.
+    #[serde(skip)]
+    light: LightMap,
+    /// Previously computed sight FOVs.
+    #[serde(skip)]
+    fov_cache: RefCell<FovCache>,
+    /// Counter that goes up whenever terrain or portals change.
+    #[serde(skip)]
+    map_revision: u64,
 }
 
 impl<'a> World {
@@ -61,43 +86,159 @@ impl<'a> World {
             version: GAME_VERSION.to_string(),
             ecs: Ecs::new(),
             worldgen: Worldgen::new(seed),
+            terrain: Field::new(),
+            clouds: Field::new(),
             spatial: Spatial::new(),
             flags: Flags::new(),
+            identification: Identification::default(),
             rng: SeedableRng::from_seed([seed, seed, seed, seed]),
             events: Vec::new(),
+            light: LightMap::default(),
+            fov_cache: RefCell::new(FovCache::default()),
+            map_revision: 0,
         };
 
-        // XXX: Clone to not run into borrow checker...
-        for (loc, spawn) in ret.worldgen
-            .spawns()
-            .cloned()
-            .collect::<Vec<(Location, Loadout)>>()
-            .into_iter()
-        {
-            ret.spawn(&spawn, loc);
-        }
+        ret.identification = Identification::new(&mut ret.rng);
 
         let player_entry = ret.worldgen.player_entry();
+        ret.spawn_generated();
         ret.spawn_player(player_entry);
 
         ret
     }
 
+    /// Make a portal at location point to the final destination if there's another portal at
+    /// its destination.
+    ///
+    /// Returns `None` if the resulting portal does not go anywhere.
+    fn chain_portal(&self, loc: Location, portal: Portal) -> Option<Portal> {
+        let dest = loc + portal;
+        let dest = self.portal(dest).unwrap_or(dest);
+        if dest == loc { None } else { Some(Portal::new(loc, dest)) }
+    }
+
+    /// Return what the entities at location look like to an observer.
+    fn appearances_at(&self, observer: Entity, loc: Location) -> Vec<Appearance> {
+        self.entities_at(loc)
+            .into_iter()
+            // Traps are remembered separately once they are found.
+            .filter(|&e| e != observer && !self.is_trap(e))
+            .filter_map(|e| {
+                self.ecs.desc.get(e).map(|desc| {
+                    Appearance {
+                        icon: desc.icon,
+                        is_mob: self.is_mob(e),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Return the sight FOV from origin, reusing a previous result if nothing in it has changed.
+    fn sight_fov(&self, origin: Location, range: u32) -> FovCells {
+        self.fov_cache.borrow_mut().get(self, origin, range)
+    }
+
+    /// Spawn the entities of map regions that have been generated since the last call.
+    fn spawn_generated(&mut self) {
+        for (loc, spawn) in self.worldgen.take_spawns() {
+            self.spawn(&spawn, loc);
+        }
+
+        for (loc, spawn) in self.worldgen.take_contents() {
+            if let Some(container) = self.container_at(loc) {
+                let e = spawn.make(&mut self.ecs);
+                self.put_in(e, container);
+            }
+        }
+    }
+
+    /// Apply clouds to whatever they cover and spread them for the next turn.
+    fn update_clouds(&mut self) {
+        let clouds: Vec<(Location, Cloud)> = self.clouds
+            .iter()
+            .filter_map(|(&loc, &c)| c.map(|c| (loc, c)))
+            .collect();
+        if clouds.is_empty() {
+            return;
+        }
+
+        // Only redo FOV if something that blocks sight appears or goes away.
+        let mut sight_changed = false;
+
+        for &(loc, cloud) in &clouds {
+            if let Some(effect) = cloud.effect() {
+                // Fire can burn away sight-blocking terrain.
+                let blocked = self.terrain(loc).blocks_sight();
+                self.apply_effect_to(&effect, loc, None);
+                sight_changed |= self.terrain(loc).blocks_sight() != blocked;
+            }
+        }
+
+        let next = cloud::step(
+            &self.clouds,
+            |loc| !self.terrain(loc).blocks_walk(),
+            |loc| self.terrain(loc).damaged(Damage::Fire).is_some(),
+        );
+
+        // New smoke shows up in the next field, cleared smoke was in the old one.
+        {
+            let smoke = |c: Option<Cloud>| c.map_or(false, |c| c.blocks_sight());
+            let mut fov_cache = self.fov_cache.borrow_mut();
+            for (&loc, _) in self.clouds.iter().chain(next.iter()) {
+                if smoke(self.clouds.get(loc)) != smoke(next.get(loc)) {
+                    fov_cache.invalidate(loc);
+                    sight_changed = true;
+                }
+            }
+        }
+        self.clouds = next;
+
+        if sight_changed {
+            self.terrain_changed();
+        }
+    }
+
+    /// Return a value that changes whenever the map terrain or portals change.
+    ///
+    /// Things outside the world that cache map data can compare this to the value they saw last
+    /// time to tell when they need to update.
+    pub fn map_revision(&self) -> u64 { self.map_revision }
+
+    /// Load a saved game.
+    ///
+    /// Saves from other versions of the game are rejected with an error, there is no conversion
+    /// of old save files.
     pub fn load<R: Read>(reader: &mut R) -> Result<World> {
-        let ret: ron::de::Result<World> = ron::de::from_reader(reader);
-        if let Ok(ref x) = ret {
-            if x.version != GAME_VERSION {
-                panic!(
+        let mut save = String::new();
+        reader.read_to_string(&mut save)?;
+
+        // Check the version before parsing the rest, saves from other versions usually won't
+        // parse at all and the error from that would say nothing useful.
+        match save_version(&save) {
+            Some(version) if version == GAME_VERSION => {}
+            Some(version) => {
+                bail!(
                     "Save game version {} does not match current version {}",
-                    x.version,
+                    version,
                     GAME_VERSION
-                );
+                )
             }
+            None => bail!("Save game has no version, not a save file?"),
         }
-        Ok(ret?)
+
+        let mut ret: World = ron::de::from_str(&save)?;
+        // Light map isn't saved, rebuild it.
+        if let Some(player) = ret.player() {
+            ret.do_fov(player);
+        }
+        Ok(ret)
     }
 
-    pub fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+    pub fn save<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        // Sectors are saved as generated, so their pending spawns must be in the world before
+        // saving or they would never show up.
+        self.spawn_generated();
         let enc = ron::ser::to_string(self)?;
         // TODO: Handle error from writer too...
         writeln!(writer, "{}", enc)?;
@@ -106,25 +247,46 @@ impl<'a> World {
 }
 
 impl TerrainQuery for World {
-    fn is_valid_location(&self, _loc: Location) -> bool {
-        //Location::origin().v2_at(loc).map_or(false, ::on_screen)
-        true
+    fn is_valid_location(&self, loc: Location) -> bool {
+        // The open sea around the surface map goes on forever, don't let anything wander off
+        // into it.
+        loc.z != 0 || overworld::contains(loc.sector())
     }
 
     fn terrain(&self, loc: Location) -> Terrain {
-        let mut t = self.worldgen.get_terrain(loc);
+        self.terrain.get(loc).unwrap_or_else(
+            || self.worldgen.get_terrain(loc),
+        )
+    }
 
-        if t == Terrain::Door && self.has_mobs(loc) {
-            // Standing in the doorway opens the door.
-            t = Terrain::OpenDoor;
-        }
+    fn portal(&self, loc: Location) -> Option<Location> { self.worldgen.get_portal(loc) }
 
-        t
+    fn is_border_portal(&self, loc: Location) -> bool {
+        self.worldgen.get_map_portal(loc).map_or(
+            false,
+            |p| p.is_border(),
+        )
     }
 
-    fn portal(&self, loc: Location) -> Option<Location> { self.worldgen.get_portal(loc) }
+    fn is_untouched(&self, loc: Location) -> bool { !self.worldgen.is_generated(loc.sector()) }
+}
 
-    fn is_untouched(&self, _loc: Location) -> bool { unimplemented!() }
+/// Read the game version a save file was made with.
+///
+/// The version is the first field of the saved world, so look for it at the start of the save
+/// without trying to make sense of the rest.
+fn save_version(save: &str) -> Option<&str> {
+    const FIELD: &'static str = "version";
+
+    let rest = match save.find(FIELD) {
+        Some(i) => &save[i + FIELD.len()..],
+        None => return None,
+    };
+    let rest = rest.trim_left_matches(|c: char| c == ':' || c.is_whitespace());
+    if !rest.starts_with('"') {
+        return None;
+    }
+    rest[1..].find('"').map(|end| &rest[1..end + 1])
 }
 
 impl Query for World {
@@ -154,10 +316,16 @@ impl Query for World {
 
     fn entities_at(&self, loc: Location) -> Vec<Entity> { self.spatial.entities_at(loc) }
 
+    fn entities_around(&self, center: Location, radius: u32) -> Vec<Entity> {
+        self.spatial.entities_around(center, radius)
+    }
+
     fn entities_in(&self, parent: Entity) -> Vec<Entity> { self.spatial.entities_in(parent) }
 
     fn ecs(&self) -> &Ecs { &self.ecs }
 
+    fn identification(&self) -> &Identification { &self.identification }
+
     fn entity_equipped(&self, parent: Entity, slot: Slot) -> Option<Entity> {
         self.spatial.entity_equipped(parent, slot)
     }
@@ -169,13 +337,25 @@ impl Query for World {
     fn sphere_volume(&self, origin: Location, radius: u32) -> Volume {
         Volume::sphere(self, origin, radius)
     }
+
+    fn cloud(&self, loc: Location) -> Option<Cloud> { self.clouds.get(loc) }
+
+    fn light_level(&self, loc: Location) -> u32 { self.light.get(loc) }
+
+    fn fov_from(&self, origin: Location, range: u32) -> HashSet<Location> {
+        HashSet::from_iter(self.sight_fov(origin, range).iter().map(|&(_, loc)| loc))
+    }
 }
 
 impl Mutate for World {
     fn next_tick(&mut self) -> CommandResult {
         use std::mem;
 
+        // Queries may have generated new parts of the map since last turn.
+        self.spawn_generated();
+
         self.ai_main();
+        self.update_clouds();
 
         self.clean_dead();
         self.flags.tick += 1;
@@ -192,12 +372,15 @@ impl Mutate for World {
         self.spatial.equip(e, parent, slot)
     }
 
+    fn put_in(&mut self, e: Entity, container: Entity) {
+        self.spatial.insert(e, Place::In(container, None))
+    }
+
     fn set_player(&mut self, player: Option<Entity>) { self.flags.player = player; }
 
     fn spawn(&mut self, loadout: &Loadout, loc: Location) -> Entity {
         let e = loadout.make(&mut self.ecs);
-        self.place_entity(e, loc);
-        e
+        self.place_entity(e, loc)
     }
 
     fn kill_entity(&mut self, e: Entity) { self.spatial.remove(e); }
@@ -210,42 +393,146 @@ impl Mutate for World {
         }
 
         if let Some(loc) = self.location(e) {
-            const DEFAULT_FOV_RANGE: u32 = 12;
-
-            let fov: HashSet<Location> = HashSet::from_iter(
-                HexFov::new(SightFov::new(self, DEFAULT_FOV_RANGE, loc))
-                    .map(|(pos, a)| a.origin + pos),
-            );
-
-            let memory = &mut self.ecs.map_memory[e];
-            memory.seen.clear();
+            let range = self.sight_range(e);
+            let cells = self.sight_fov(loc, range);
+            let area = HashSet::from_iter(cells.iter().map(|&(_, loc)| loc));
+            let light = LightMap::new(self, loc, range, &area);
+
+            // Dark cells can't be seen, except for the ones right next to the viewer.
+            let fov: Vec<Location> = cells
+                .iter()
+                .filter(|&&(pos, loc)| pos.hex_dist() <= 1 || light.get(loc) > 0)
+                .map(|&(_, loc)| loc)
+                .collect();
+
+            let appearances: Vec<(Location, Vec<Appearance>)> = fov.iter()
+                .map(|&loc| (loc, self.appearances_at(e, loc)))
+                .collect();
+
+            {
+                let memory = &mut self.ecs.map_memory[e];
+                // Usually nothing has changed since the last turn, skip rebuilding the sets then.
+                let unchanged = memory.seen.len() == fov.len() &&
+                    fov.iter().all(|loc| memory.seen.contains(loc));
+                if !unchanged {
+                    memory.seen = LocationSet::from_iter(fov.iter().cloned());
+                    memory.remembered.extend(fov.iter().cloned());
+                }
+
+                for (loc, seen) in appearances {
+                    if seen.is_empty() {
+                        memory.entities.remove(&loc);
+                    } else {
+                        memory.entities.insert(loc, seen);
+                    }
+                }
+            }
 
-            for &loc in &fov {
-                memory.seen.insert(loc);
-                memory.remembered.insert(loc);
+            if self.flags.player == Some(e) {
+                self.light = light;
             }
         }
     }
 
+    fn set_cloud(&mut self, loc: Location, cloud: Option<Cloud>) {
+        if self.clouds.get(loc).map_or(false, |c| c.blocks_sight()) !=
+            cloud.map_or(false, |c| c.blocks_sight())
+        {
+            self.fov_cache.borrow_mut().invalidate(loc);
+        }
+        self.clouds.set(loc, cloud);
+    }
+
     fn push_event(&mut self, event: Event) { self.events.push(event); }
 
     fn rng(&mut self) -> &mut Rng { &mut self.rng }
 
     fn ecs_mut(&mut self) -> &mut Ecs { &mut self.ecs }
+
+    fn identification_mut(&mut self) -> &mut Identification { &mut self.identification }
 }
 
 impl Command for World {}
 
 impl Terraform for World {
-    fn set_terrain(&mut self, _loc: Location, _terrain: Terrain) {
-        unimplemented!();
+    fn set_terrain(&mut self, loc: Location, terrain: Terrain) {
+        let old = self.terrain(loc);
+        if old.form() == terrain::Form::Void || terrain.form() == terrain::Form::Void {
+            // Void terrain opens and closes portals, which can affect FOVs anywhere.
+            self.fov_cache.borrow_mut().clear();
+        } else if old != terrain {
+            let mut fov_cache = self.fov_cache.borrow_mut();
+            fov_cache.invalidate(loc);
+            // Walls next to a FOV get included in it as fake isometric walls.
+            for &d in Dir6::iter() {
+                fov_cache.invalidate(loc + d);
+            }
+        }
+        if old != terrain {
+            self.map_revision += 1;
+        }
+        self.terrain.set(loc, Some(terrain));
+    }
+
+    fn set_portal(&mut self, loc: Location, portal: Portal) {
+        self.fov_cache.borrow_mut().clear();
+        self.map_revision += 1;
+        // A portal that doesn't go anywhere replaces whatever portal was there before.
+        let portal = self.chain_portal(loc, portal).map(MapPortal::Hole);
+        self.worldgen.set_portal(loc, portal);
     }
 
-    fn set_portal(&mut self, _loc: Location, _portal: Portal) {
-        unimplemented!();
+    fn set_border_portal(&mut self, loc: Location, portal: Portal) {
+        self.fov_cache.borrow_mut().clear();
+        self.map_revision += 1;
+        let portal = self.chain_portal(loc, portal).map(MapPortal::Border);
+        self.worldgen.set_portal(loc, portal);
+    }
+
+    fn remove_portal(&mut self, loc: Location) {
+        self.fov_cache.borrow_mut().clear();
+        self.map_revision += 1;
+        self.worldgen.set_portal(loc, None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GAME_VERSION, World};
+    use location::Sector;
+    use query::Query;
+    use terraform::TerrainQuery;
+
+    #[test]
+    fn test_save_pending_spawns() {
+        let mut world = World::new(1);
+        let level = Sector::new(0, 0, 1);
+        let on_level = |w: &World| {
+            w.entities()
+                .filter(|&&e| w.location(e).map_or(false, |loc| loc.sector() == level))
+                .count()
+        };
+
+        // Generate the level without letting a turn pass to spawn its contents.
+        world.terrain(level.center());
+        assert_eq!(on_level(&world), 0);
+
+        let mut saved = Vec::new();
+        world.save(&mut saved).unwrap();
+        let loaded = World::load(&mut &saved[..]).unwrap();
+        assert!(on_level(&loaded) > 0);
     }
 
-    fn remove_portal(&mut self, _loc: Location) {
-        unimplemented!();
+    #[test]
+    fn test_load_other_version() {
+        let mut world = World::new(1);
+        let mut saved = Vec::new();
+        world.save(&mut saved).unwrap();
+        let saved = String::from_utf8(saved).unwrap();
+        assert!(World::load(&mut saved.as_bytes()).is_ok());
+
+        let old = saved.replacen(GAME_VERSION, "0.0.1", 1);
+        let err = World::load(&mut old.as_bytes()).err().unwrap();
+        assert!(format!("{}", err).contains("0.0.1"));
     }
 }
@@ -1,48 +1,301 @@
 use Prefab;
 use Rng;
-use calx_grid::{Dijkstra, Dir6};
+use calx_grid::{Dijkstra, Dir6, HexGeom};
 use euclid::vec2;
 use field::Field;
 use form::{self, Form};
-use location::{Location, Portal};
+use item::ItemType;
+use location::{Location, Portal, Sector, SECTOR_HEIGHT, SECTOR_WIDTH};
+use overworld::{self, Overworld};
 use rand::{self, SeedableRng};
 use serde;
+use std::cell::RefCell;
 use std::cmp;
-use std::collections::BTreeSet;
-use std::collections::HashMap;
-use std::slice;
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use terrain::Terrain;
 use world::Loadout;
 
-/// Static generated world.
+/// Dungeon branches going down from the surface, as sector x coordinate and depth.
+///
+/// Each branch has its own column of sectors so that the levels of different branches never
+/// touch each other.
+const DUNGEONS: [(i16, i16); 3] = [(0, 10), (2, 3), (-2, 3)];
+
+/// Generated world.
+///
+/// Map content is generated lazily a region of sectors at a time when something first asks about
+/// it. Generation is deterministic for a given seed, so only the seed and the set of sectors whose
+/// entities have already been spawned need to be saved.
 pub struct Worldgen {
     seed: u32,
-    terrain: HashMap<Location, Terrain>,
-    portals: HashMap<Location, Portal>,
-    spawns: Vec<(Location, Loadout)>,
-    player_entry: Location,
+    /// Sectors that have been generated during the game.
+    generated: RefCell<BTreeSet<Sector>>,
+    /// Sectors whose map data is currently in memory.
+    loaded: RefCell<HashSet<Sector>>,
+    maps: RefCell<Maps>,
+    /// Portals changed during the game, `None` values mark removed portals.
+    portal_edits: BTreeMap<Location, Option<MapPortal>>,
+}
+
+/// Portal stored in the map.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum MapPortal {
+    /// Portal at the edge of a patch of local terrain, map memory display continues seamlessly
+    /// past it.
+    Border(Portal),
+    /// Portal surrounded by local terrain, like a stairwell.
+    Hole(Portal),
+}
+
+impl MapPortal {
+    pub fn portal(self) -> Portal {
+        match self {
+            MapPortal::Border(p) | MapPortal::Hole(p) => p,
+        }
+    }
+
+    pub fn is_border(self) -> bool {
+        match self {
+            MapPortal::Border(_) => true,
+            MapPortal::Hole(_) => false,
+        }
+    }
 }
 
 impl Worldgen {
     pub fn new(seed: u32) -> Worldgen {
-        let mut ret = Worldgen {
+        Worldgen {
             seed: seed,
-            terrain: HashMap::new(),
-            portals: HashMap::new(),
-            spawns: Vec::new(),
-            player_entry: Location::new(0, 0, 0),
+            generated: RefCell::new(BTreeSet::new()),
+            loaded: RefCell::new(HashSet::new()),
+            maps: RefCell::new(Maps::default()),
+            portal_edits: BTreeMap::new(),
+        }
+    }
+
+    /// Make sure the map content for the sector has been generated.
+    fn generate(&self, sector: Sector) {
+        if self.loaded.borrow().contains(&sector) {
+            return;
+        }
+
+        let region = Region::new(sector);
+        let key = region.sector();
+        // Unique random sequence for each region that doesn't depend on the order in which the
+        // regions get generated. Keep the second word nonzero, an all-zero seed is not allowed.
+        let mut rng: Rng = SeedableRng::from_seed([
+            self.seed,
+            1 + key.x as u16 as u32,
+            key.y as u16 as u32,
+            key.z as u16 as u32,
+        ]);
+
+        let mut maps = Maps::default();
+        match region {
+            Region::Surface => maps.gen_surface(&mut rng, self.seed),
+            Region::Level(sector, depth) => {
+                let stairs = self.stairs_down_to(sector);
+                maps.gen_dungeon_level(&mut rng, sector, depth, stairs)
+            }
+            Region::Empty(_) => {}
+        }
+
+        let sectors = region.sectors();
+        // Entities only get spawned the first time around, when loading a saved game they are
+        // already in the world.
+        let is_new = !self.generated.borrow().contains(&key);
+        self.loaded.borrow_mut().extend(sectors.iter().cloned());
+        self.generated.borrow_mut().extend(sectors.into_iter());
+        self.maps.borrow_mut().merge(maps, is_new);
+    }
+
+    /// Return the location of the stairs on the level above that lead down to a dungeon level.
+    ///
+    /// Generates the level above if it hasn't been generated yet.
+    fn stairs_down_to(&self, sector: Sector) -> Location {
+        let above = if sector.z == 1 {
+            Sector::new(0, 0, 0)
+        } else {
+            Sector::new(sector.x, sector.y, sector.z - 1)
         };
+        self.generate(above);
+
+        *self.maps.borrow().stairs_down.get(&sector).expect(
+            "No stairs leading down to dungeon level",
+        )
+    }
+
+    pub fn seed(&self) -> u32 { self.seed }
+
+    pub fn get_terrain(&self, loc: Location) -> Terrain {
+        self.generate(loc.sector());
+        if let Some(&t) = self.maps.borrow().terrain.get(&loc) {
+            t
+        } else {
+            default_terrain(loc)
+        }
+    }
+
+    pub fn get_portal(&self, loc: Location) -> Option<Location> {
+        self.get_map_portal(loc).map(|p| loc + p.portal())
+    }
+
+    pub fn get_map_portal(&self, loc: Location) -> Option<MapPortal> {
+        if let Some(&edit) = self.portal_edits.get(&loc) {
+            return edit;
+        }
+
+        self.generate(loc.sector());
+        self.maps.borrow().portals.get(&loc).cloned()
+    }
+
+    /// Set or remove a portal, the change will be saved with the game.
+    pub fn set_portal(&mut self, loc: Location, portal: Option<MapPortal>) {
+        self.portal_edits.insert(loc, portal);
+    }
+
+    /// Return whether the sector has been generated during the game.
+    pub fn is_generated(&self, sector: Sector) -> bool { self.generated.borrow().contains(&sector) }
+
+    /// Remove and return entity spawns from newly generated regions.
+    pub fn take_spawns(&mut self) -> Vec<(Location, Loadout)> {
+        mem::replace(&mut self.maps.borrow_mut().spawns, Vec::new())
+    }
+
+    /// Remove and return item spawns that go inside the containers spawned at their locations.
+    pub fn take_contents(&mut self) -> Vec<(Location, Loadout)> {
+        mem::replace(&mut self.maps.borrow_mut().contents, Vec::new())
+    }
+
+    pub fn player_entry(&self) -> Location {
+        self.generate(Sector::new(0, 0, 0));
+        self.maps.borrow().player_entry.expect(
+            "Surface map has no player entry",
+        )
+    }
+}
+
+fn default_terrain(loc: Location) -> Terrain {
+    if loc.z == 0 {
+        // Open sea around the generated surface area.
+        Terrain::Water
+    } else {
+        Terrain::Rock
+    }
+}
+
+/// Unit of map generation.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Region {
+    /// The whole surface map.
+    Surface,
+    /// A dungeon level with the depth of its branch.
+    Level(Sector, i16),
+    /// A sector with no content.
+    Empty(Sector),
+}
+
+impl Region {
+    fn new(sector: Sector) -> Region {
+        if overworld::contains(sector) {
+            return Region::Surface;
+        }
+
+        // The stairs leading up from a level are just past its top edge, so the level also owns
+        // the sector above it.
+        if (sector.y == 0 || sector.y == -1) && sector.z > 0 {
+            for &(x, depth) in &DUNGEONS {
+                if sector.x == x && sector.z <= depth {
+                    return Region::Level(Sector::new(x, 0, sector.z), depth);
+                }
+            }
+        }
+
+        Region::Empty(sector)
+    }
+
+    /// Sector that identifies the region.
+    fn sector(self) -> Sector {
+        match self {
+            Region::Surface => Sector::new(0, 0, 0),
+            Region::Level(sector, _) | Region::Empty(sector) => sector,
+        }
+    }
+
+    fn sectors(self) -> Vec<Sector> {
+        match self {
+            Region::Surface => overworld::sectors(),
+            Region::Level(sector, _) => {
+                vec![sector, Sector::new(sector.x, sector.y - 1, sector.z)]
+            }
+            Region::Empty(sector) => vec![sector],
+        }
+    }
+}
+
+/// Map data from generating regions.
+#[derive(Default)]
+struct Maps {
+    terrain: HashMap<Location, Terrain>,
+    portals: HashMap<Location, MapPortal>,
+    spawns: Vec<(Location, Loadout)>,
+    /// Locations of stairs going down, keyed by the sector of the dungeon level they lead to.
+    stairs_down: HashMap<Sector, Location>,
+    /// Items to put in containers spawned at the same location.
+    contents: Vec<(Location, Loadout)>,
+    player_entry: Option<Location>,
+}
+
+impl Maps {
+    fn merge(&mut self, other: Maps, with_spawns: bool) {
+        self.terrain.extend(other.terrain);
+        self.portals.extend(other.portals);
+        self.stairs_down.extend(other.stairs_down);
+        if with_spawns {
+            self.spawns.extend(other.spawns);
+            self.contents.extend(other.contents);
+        }
+        if other.player_entry.is_some() {
+            self.player_entry = other.player_entry;
+        }
+    }
 
-        let mut rng: Rng = SeedableRng::from_seed([seed, seed, seed, seed]);
+    fn gen_surface<R: rand::Rng>(&mut self, rng: &mut R, seed: u32) {
+        let overworld = Overworld::new(rng, seed, DUNGEONS.len());
+        self.terrain.extend(overworld.terrain);
+        for (loc, spawn) in overworld.spawns {
+            self.add_spawn(loc, &spawn);
+        }
 
-        let mut cave_entrance = Location::new(9, 0, 0);
+        for (&(sector_x, _), &entrance) in DUNGEONS.iter().zip(overworld.dungeon_entrances.iter()) {
+            self.cave_entrance(entrance, Sector::new(sector_x, 0, 1).origin());
+        }
+    }
 
-        for cave_z in 1..11 {
-            ret.cave_entrance(cave_entrance, Location::new(0, 0, cave_z));
-            cave_entrance = ret.gen_caves(&mut rng, Location::new(0, 0, cave_z));
+    /// Generate a dungeon level with stairs going back up to `stairs` on the level above.
+    fn gen_dungeon_level<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+        sector: Sector,
+        depth: i16,
+        stairs: Location,
+    ) {
+        let entrance = sector.origin();
+        if stairs.z == 0 {
+            self.portal(entrance - vec2(1, 1), stairs);
+        } else {
+            self.border_portal(entrance - vec2(1, 1), stairs);
         }
 
-        ret
+        let exit = self.gen_level(rng, entrance, LevelType::at_depth(sector.z as i32));
+
+        // The next level's entrance is always at the same spot, so the stairs can be built
+        // before the next level has been generated.
+        if sector.z < depth {
+            self.cave_entrance(exit, Sector::new(sector.x, sector.y, sector.z + 1).origin());
+        }
     }
 
     fn load_prefab(&mut self, origin: Location, prefab: &Prefab) {
@@ -52,56 +305,87 @@ impl Worldgen {
             self.terrain.insert(loc, *terrain);
 
             for spawn in entities.iter() {
-                if spawn == "player" {
-                    self.player_entry = loc;
-                } else {
-                    let form = Form::named(spawn).expect(&format!(
-                        "Bad prefab: Form '{}' not found!",
-                        spawn
-                    ));
-                    self.spawns.push((loc, form.loadout.clone()));
-                }
+                self.add_spawn(loc, spawn);
             }
         }
     }
 
-    pub fn seed(&self) -> u32 { self.seed }
-
-    pub fn get_terrain(&self, loc: Location) -> Terrain {
-        if let Some(&t) = self.terrain.get(&loc) {
-            t
+    /// Add a spawn by form name, the special name "player" sets the player entry point.
+    fn add_spawn(&mut self, loc: Location, spawn: &str) {
+        if spawn == "player" {
+            self.player_entry = Some(loc);
         } else {
-            self.default_terrain(loc)
+            let form = Form::named(spawn).expect(&format!(
+                "Bad prefab: Form '{}' not found!",
+                spawn
+            ));
+            self.spawns.push((loc, form.loadout.clone()));
         }
     }
 
-    fn default_terrain(&self, loc: Location) -> Terrain {
-        use Terrain::*;
-        if loc.z == 0 {
-            match loc.noise() {
-                n if n > 0.8 => Tree,
-                n if n > -0.8 => Grass,
-                _ => Water,
+    /// Generate an underground level and return the location of its exit.
+    ///
+    /// Will always succeed with a level where the exit can be reached from the entrance.
+    fn gen_level<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+        entrance: Location,
+        level_type: LevelType,
+    ) -> Location {
+        // The carved exit fallback should catch pretty much everything, but if it doesn't, just
+        // throw the level out and try again with the RNG in a new state.
+        const MAX_ATTEMPTS: usize = 100;
+
+        for _ in 0..MAX_ATTEMPTS {
+            let exit_loc = match level_type {
+                LevelType::Caves => self.try_gen_caves(rng, entrance),
+                LevelType::Rooms => self.try_gen_rooms(rng, entrance),
+            };
+
+            if let Some(exit_loc) = exit_loc {
+                return exit_loc;
             }
-        } else {
-            Terrain::Rock
         }
-    }
 
-    pub fn get_portal(&self, loc: Location) -> Option<Location> {
-        self.portals.get(&loc).map(|&p| loc + p)
+        // Still nothing, settle for a level that can't fail instead of crashing the game.
+        self.gen_fallback_level(entrance)
     }
 
-    pub fn spawns(&self) -> slice::Iter<(Location, Loadout)> { self.spawns.iter() }
+    /// Generate a bare corridor from the entrance down to the exit.
+    ///
+    /// Last resort when regular cave generation keeps failing.
+    fn gen_fallback_level(&mut self, entrance: Location) -> Location {
+        use self::Prototerrain::*;
+
+        let mut map = screen_map(entrance.sector().origin());
+        entry_cave_enclosure(&mut map, entrance);
+        let entrance = entrance + vec2(1, 1);
+
+        // Veer off the sector edge first, then head straight down until the exit fits.
+        let mut exit_loc = entrance;
+        for step in 0.. {
+            exit_loc = exit_loc + if step < 4 { Dir6::Southeast } else { Dir6::South };
+            if can_be_path_down_opening(&map, entrance, exit_loc) {
+                break;
+            }
+            map.set(exit_loc, Floor);
+        }
+        map.set(exit_loc, Border);
 
-    pub fn player_entry(&self) -> Location { self.player_entry }
+        self.write_terrain(&map, Terrain::Rock);
+        exit_loc
+    }
 
-    fn gen_caves<R: rand::Rng>(&mut self, rng: &mut R, entrance: Location) -> Location {
+    fn try_gen_caves<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+        entrance: Location,
+    ) -> Option<Location> {
         use self::Prototerrain::*;
 
         let mut cells_to_dig = 700;
 
-        let mut map = screen_map(Location::new(0, 0, entrance.z));
+        let mut map = screen_map(entrance.sector().origin());
 
         debug_assert_eq!(map.get(entrance), Unused);
 
@@ -110,8 +394,10 @@ impl Worldgen {
 
         let entrance = entrance + vec2(1, 1);
 
+        // Start digging right next to the enclosure mouth so that the whole cave stays connected
+        // to the entrance.
         let mut edge: BTreeSet<Location> = Dir6::iter()
-            .map(|&d| entrance + vec2(2, 2) + d)
+            .map(|&d| entrance + d)
             .filter(|&loc| map.get(loc) == Unused)
             .collect();
 
@@ -122,7 +408,7 @@ impl Worldgen {
                 break;
             }
 
-            let dig_loc = *rand::sample(rng, edge.iter(), 1)[0];
+            let dig_loc = *edge.iter().nth(rng.gen_range(0, edge.len())).unwrap();
 
             // Prefer digging narrow corridors, there's an increasing chance to abort the dig when the
             // selected location is in a very open space.
@@ -157,16 +443,109 @@ impl Worldgen {
             }
         }
 
+        self.finish_level(rng, map, entrance, Terrain::Rock)
+    }
+
+    fn try_gen_rooms<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+        entrance: Location,
+    ) -> Option<Location> {
+        use self::Prototerrain::*;
+
+        const MIN_ROOMS: usize = 4;
+        const MAX_ROOMS: usize = 9;
+
+        let sector = entrance.sector();
+        let mut map = screen_map(sector.origin());
+
+        debug_assert_eq!(map.get(entrance), Unused);
+
+        entry_cave_enclosure(&mut map, entrance);
+
+        let entrance = entrance + vec2(1, 1);
+
+        let mut rooms = Vec::new();
+        for _ in 0..200 {
+            if rooms.len() >= MAX_ROOMS {
+                break;
+            }
+
+            let room = Room::rand(rng, sector);
+            if room.fits(&map) {
+                room.dig(&mut map);
+                rooms.push(room);
+            }
+        }
+
+        if rooms.len() < MIN_ROOMS {
+            return None;
+        }
+
+        let room_walls: HashSet<Location> = rooms
+            .iter()
+            .flat_map(|r| r.walls.iter().cloned())
+            .collect();
+
+        // Chain the rooms together starting from the entrance, then add a few extra connections
+        // so the map isn't just a single long path.
+        rooms.sort_by_key(|r| r.center.metric_distance(entrance));
+        let mut prev = entrance;
+        for room in &rooms {
+            if !dig_corridor(&mut map, &room_walls, prev, room.center) {
+                return None;
+            }
+            prev = room.center;
+        }
+
+        for _ in 0..(rooms.len() / 3) {
+            let a = rooms[rng.gen_range(0, rooms.len())].center;
+            let b = rooms[rng.gen_range(0, rooms.len())].center;
+            if a != b {
+                // Extra loops are optional, don't care if this fails.
+                dig_corridor(&mut map, &room_walls, a, b);
+            }
+        }
+
+        if rng.gen_range(0, 3) == 0 {
+            // Lock up the room furthest from the entrance. A key will be placed outside it.
+            let vault = rooms.last().expect("No rooms");
+            for &loc in &vault.walls {
+                if map.get(loc) == Door {
+                    map.set(loc, LockedDoor);
+                }
+            }
+        }
+
+        self.finish_level(rng, map, entrance, Terrain::Wall)
+    }
+
+    /// Place exit and spawns on a generated map and write it into the world terrain.
+    ///
+    /// Returns the exit location or `None` if no exit could be placed.
+    fn finish_level<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+        mut map: Field<Prototerrain>,
+        entrance: Location,
+        wall: Terrain,
+    ) -> Option<Location> {
+        use self::Prototerrain::*;
 
         // Find opening for next map
         let openings: Vec<Location> = map.iter()
             .map(|(&loc, _)| loc)
             .filter(|&loc| can_be_path_down_opening(&map, entrance, loc))
             .collect();
-        // XXX FIXME: There's actually no guarantees made that this can't fail
-        // Fallback should be something like carving the exit to the bottom edge of the map
-        assert!(!openings.is_empty(), "No exit candidates");
-        let exit_loc = rand::sample(rng, openings, 1)[0];
+        let exit_loc = if openings.is_empty() {
+            // No natural exit site, dig one towards the bottom edge of the map.
+            match carve_exit(&mut map, entrance) {
+                Some(loc) => loc,
+                None => return None,
+            }
+        } else {
+            rand::sample(rng, openings, 1)[0]
+        };
         map.set(exit_loc, Border);
 
 
@@ -174,10 +553,16 @@ impl Worldgen {
         const MIN_DISTANCE_FROM_ENTRANCE: u32 = 10;
         let depth = entrance.z as i32;
         // Flood-fill the new map
-        let mut spawn_map = Dijkstra::new(vec![entrance], |&loc| map.get(loc) == Floor, 10_000)
-            .weights;
+        let mut spawn_map =
+            Dijkstra::new(vec![entrance], |&loc| map.get(loc).is_walkable(), 10_000).weights;
         // Filter stuff too close to entrance
         spawn_map.retain(|_, &mut w| w >= MIN_DISTANCE_FROM_ENTRANCE);
+        // Traps go in corridors where they're hard to avoid.
+        let corridors: Vec<Location> = spawn_map
+            .keys()
+            .cloned()
+            .filter(|&loc| map.get(loc) == Corridor)
+            .collect();
         // Don't need weights anymore, convert to Vec.
         let mut spawn_locs: Vec<Location> = spawn_map.into_iter().map(|(loc, _)| loc).collect();
         // Filter stuff next to walls, only spawn in open areas
@@ -187,6 +572,31 @@ impl Worldgen {
                 .sum::<u32>() > 3
         });
 
+        let mut key_loc = None;
+        if map.iter().any(|(_, &t)| t == LockedDoor) {
+            // Make sure the locked doors can be opened, put the key somewhere the player can
+            // reach without going through them.
+            let open_area = Dijkstra::new(
+                vec![entrance],
+                |&loc| map.get(loc) != LockedDoor && map.get(loc).is_walkable(),
+                10_000,
+            ).weights;
+            let key_locs: Vec<Location> = spawn_locs
+                .iter()
+                .cloned()
+                .filter(|loc| open_area.contains_key(loc))
+                .collect();
+            // No safe place for the key, the level is no good.
+            let loc = match rng.choose(&key_locs) {
+                Some(&loc) => loc,
+                None => return None,
+            };
+            spawn_locs.retain(|&l| l != loc);
+            let key = Form::named("key").expect("No key form");
+            self.spawns.push((loc, key.loadout.clone()));
+            key_loc = Some(loc);
+        }
+
         let mut spawn_locs = rand::sample(rng, spawn_locs.iter(), 20);
         let n_spawns = spawn_locs.len();
 
@@ -196,6 +606,44 @@ impl Worldgen {
                 loc,
                 form::rand(rng, &items)
                     .expect("No item spawn")
+                    .roll_loadout(rng, depth),
+            ))
+        }
+
+        const MAX_CHEST_ITEMS: usize = 3;
+        if !spawn_locs.is_empty() && rng.gen_range(0, 3) == 0 {
+            // Things you can't carry don't go in chests.
+            let loot: Vec<&'static Form> = items
+                .iter()
+                .cloned()
+                .filter(|f| match f.loadout.item.as_ref().map(|i| i.item_type) {
+                    Some(ItemType::Instant(_)) |
+                    Some(ItemType::Container { .. }) => false,
+                    _ => true,
+                })
+                .collect();
+
+            let loc = *spawn_locs.remove(0);
+            let chest = Form::named("chest").expect("No chest form");
+            self.spawns.push((loc, chest.loadout.clone()));
+            for _ in 0..rng.gen_range(1, MAX_CHEST_ITEMS + 1) {
+                self.contents.push((
+                    loc,
+                    form::rand(rng, &loot)
+                        .expect("No item spawn")
+                        .roll_loadout(rng, depth),
+                ))
+            }
+        }
+
+        const MAX_TRAPS: usize = 3;
+        let traps = Form::filter(|f| f.is_trap() && f.at_depth(depth));
+        let n_traps = rng.gen_range(0, MAX_TRAPS + 1);
+        for &loc in rand::sample(rng, corridors.iter(), n_traps) {
+            self.spawns.push((
+                loc,
+                form::rand(rng, &traps)
+                    .expect("No trap spawn")
                     .loadout
                     .clone(),
             ))
@@ -213,41 +661,64 @@ impl Worldgen {
         }
 
 
-        // Map to actual terrains
+        // Hide some of the doors that aren't needed to get to the exit or the key.
+        let mut doors: Vec<Location> = map.iter()
+            .filter(|&(_, &t)| t == Door)
+            .map(|(&loc, _)| loc)
+            .collect();
+        rng.shuffle(&mut doors);
+        for loc in doors {
+            if rng.gen_range(0, 4) != 0 {
+                continue;
+            }
+            map.set(loc, SecretDoor);
+            let reachable =
+                Dijkstra::new(vec![entrance], |&loc| map.get(loc).is_walkable(), 10_000).weights;
+            let key_reachable = key_loc.map_or(true, |key_loc| {
+                Dijkstra::new(
+                    vec![entrance],
+                    |&loc| map.get(loc) != LockedDoor && map.get(loc).is_walkable(),
+                    10_000,
+                ).weights
+                    .contains_key(&key_loc)
+            });
+            // The exit cell itself is border terrain, check the floor cell leading to it.
+            if !reachable.contains_key(&(exit_loc + Dir6::North)) || !key_reachable {
+                map.set(loc, Door);
+            }
+        }
+
+        self.write_terrain(&map, wall);
+
+        // XXX: This thing needs to be more automatic
+        // Make the backportal cell have transparent terrain
+
+
+        Some(exit_loc)
+    }
+
+    /// Map the generator cells to actual terrains.
+    fn write_terrain(&mut self, map: &Field<Prototerrain>, wall: Terrain) {
+        use self::Prototerrain::*;
+
         self.terrain.extend(map.iter().map(|(&loc, &t)| {
             let t = match t {
                 Outside => Terrain::Empty,
-                Unused | Border | Wall => Terrain::Rock,
+                Unused | Border => Terrain::Rock,
+                Wall => wall,
                 Floor => Terrain::Ground,
+                Corridor => Terrain::Corridor,
                 Door => Terrain::Door,
+                LockedDoor => Terrain::LockedDoor,
+                SecretDoor => Terrain::SecretDoor,
             };
             (loc, t)
         }));
-
-        // XXX: This thing needs to be more automatic
-        // Make the backportal cell have transparent terrain
-
-
-        return exit_loc;
-
-        fn can_be_path_down_opening(
-            map: &Field<Prototerrain>,
-            origin: Location,
-            loc: Location,
-        ) -> bool {
-            const MIN_EXIT_DISTANCE: i32 = 12;
-
-            loc.metric_distance(origin) > MIN_EXIT_DISTANCE &&
-                map.get(loc + Dir6::North) == Floor &&
-                map.get(loc + Dir6::Northeast) != Floor &&
-                map.get(loc + Dir6::Southeast) != Floor &&
-                map.get(loc + Dir6::South) != Floor &&
-                map.get(loc + Dir6::Southwest) != Floor &&
-                map.get(loc + Dir6::Northwest) != Floor
-        }
     }
 
     /// Make a cave entrance going down.
+    ///
+    /// The level below makes the portal leading back up when it gets generated.
     fn cave_entrance(&mut self, loc: Location, cave_start: Location) {
         const DOWNBOUND_ENCLOSURE: [(i32, i32); 5] = [(1, 0), (0, 1), (2, 1), (1, 2), (2, 2)];
 
@@ -259,29 +730,51 @@ impl Worldgen {
         self.terrain.insert(loc, Terrain::Ground);
         self.terrain.insert(loc + vec2(1, 1), Terrain::Ground);
 
-        // Connect the points
-        self.portal(loc + vec2(1, 1), cave_start);
-        self.portal(cave_start - vec2(1, 1), loc);
+        if loc.z == 0 {
+            self.portal(loc + vec2(1, 1), cave_start);
+        } else {
+            self.border_portal(loc + vec2(1, 1), cave_start);
+        }
+        self.stairs_down.insert(cave_start.sector(), loc);
     }
 
-    /// Punch a (one-way) portal between two points.
+    /// Punch a (one-way) hole portal between two points.
     fn portal(&mut self, origin: Location, destination: Location) {
         self.portals.insert(
             origin,
-            Portal::new(origin, destination),
+            MapPortal::Hole(Portal::new(origin, destination)),
+        );
+    }
+
+    /// Stitch two maps together with a (one-way) border portal.
+    ///
+    /// Used for the stairs between dungeon levels, which join the bottom edge of one level to the
+    /// top edge of the next one.
+    fn border_portal(&mut self, origin: Location, destination: Location) {
+        self.portals.insert(
+            origin,
+            MapPortal::Border(Portal::new(origin, destination)),
         );
     }
 }
 
 impl serde::Serialize for Worldgen {
     fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
-        self.seed.serialize(s)
+        (self.seed, &*self.generated.borrow(), &self.portal_edits).serialize(s)
     }
 }
 
 impl<'a> serde::Deserialize<'a> for Worldgen {
     fn deserialize<D: serde::Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
-        Ok(Worldgen::new(serde::Deserialize::deserialize(d)?))
+        let (seed, generated, portal_edits): (
+            u32,
+            BTreeSet<Sector>,
+            BTreeMap<Location, Option<MapPortal>>,
+        ) = serde::Deserialize::deserialize(d)?;
+        let mut ret = Worldgen::new(seed);
+        *ret.generated.borrow_mut() = generated;
+        ret.portal_edits = portal_edits;
+        Ok(ret)
     }
 }
 
@@ -295,14 +788,181 @@ enum Prototerrain {
     /// Area that the player cannot enter but that may be visible on screen.
     Border,
     Floor,
+    Corridor,
     Wall,
     Door,
+    /// Door that needs a key to open.
+    LockedDoor,
+    /// Door that looks like a wall until it's found.
+    SecretDoor,
+}
+
+impl Prototerrain {
+    fn is_walkable(self) -> bool {
+        use self::Prototerrain::*;
+        match self {
+            Floor | Corridor | Door | LockedDoor => true,
+            _ => false,
+        }
+    }
 }
 
 impl Default for Prototerrain {
     fn default() -> Self { Prototerrain::Outside }
 }
 
+/// Type of generator used for an underground level.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum LevelType {
+    /// Natural caverns.
+    Caves,
+    /// Rooms connected with corridors.
+    Rooms,
+}
+
+impl LevelType {
+    /// Choose the level generator for a depth.
+    fn at_depth(depth: i32) -> LevelType {
+        // Caverns right below the surface, then mostly built dungeon with the occasional deep
+        // cavern level mixed in.
+        match depth {
+            d if d <= 2 => LevelType::Caves,
+            d if d % 4 == 0 => LevelType::Caves,
+            _ => LevelType::Rooms,
+        }
+    }
+}
+
+/// Room placed by the room-and-corridor generator.
+struct Room {
+    center: Location,
+    floor: Vec<Location>,
+    walls: Vec<Location>,
+}
+
+impl Room {
+    fn new(center: Location, floor: Vec<Location>) -> Room {
+        let interior: HashSet<Location> = floor.iter().cloned().collect();
+        let mut walls: Vec<Location> = floor
+            .iter()
+            .flat_map(|&loc| Dir6::iter().map(move |&d| loc + d))
+            .filter(|loc| !interior.contains(loc))
+            .collect();
+        walls.sort();
+        walls.dedup();
+
+        Room {
+            center,
+            floor,
+            walls,
+        }
+    }
+
+    /// Generate a random rectangular or hexagonal room somewhere in the sector.
+    fn rand<R: rand::Rng>(rng: &mut R, sector: Sector) -> Room {
+        if rng.gen_range(0, 3) == 0 {
+            let radius = rng.gen_range(2, 4);
+            let center = sector.rect_coord_loc(
+                rng.gen_range(radius + 1, SECTOR_WIDTH - radius - 1),
+                rng.gen_range(radius + 1, SECTOR_HEIGHT - radius - 1),
+            );
+
+            let mut floor = Vec::new();
+            for y in -radius..(radius + 1) {
+                for x in -radius..(radius + 1) {
+                    if vec2(x, y).hex_dist() <= radius {
+                        floor.push(center + vec2(x, y));
+                    }
+                }
+            }
+
+            Room::new(center, floor)
+        } else {
+            let (w, h) = (rng.gen_range(4, 10), rng.gen_range(3, 6));
+            let (u, v) = (
+                rng.gen_range(1, SECTOR_WIDTH - w - 1),
+                rng.gen_range(1, SECTOR_HEIGHT - h - 1),
+            );
+
+            let mut floor = Vec::new();
+            for j in 0..h {
+                for i in 0..w {
+                    floor.push(sector.rect_coord_loc(u + i, v + j));
+                }
+            }
+
+            Room::new(sector.rect_coord_loc(u + w / 2, v + h / 2), floor)
+        }
+    }
+
+    /// Return whether the room can be placed on the map without overlapping other content.
+    ///
+    /// Rooms are allowed to share walls.
+    fn fits(&self, map: &Field<Prototerrain>) -> bool {
+        use self::Prototerrain::*;
+
+        self.floor.iter().all(|&loc| map.get(loc) == Unused) &&
+            self.walls.iter().all(|&loc| match map.get(loc) {
+                Unused | Wall => true,
+                _ => false,
+            })
+    }
+
+    fn dig(&self, map: &mut Field<Prototerrain>) {
+        for &loc in &self.walls {
+            map.set(loc, Prototerrain::Wall);
+        }
+        for &loc in &self.floor {
+            map.set(loc, Prototerrain::Floor);
+        }
+    }
+}
+
+/// Dig a corridor between two points, punching doors where it goes through room walls.
+///
+/// Returns false if there was no way to connect the points.
+fn dig_corridor(
+    map: &mut Field<Prototerrain>,
+    room_walls: &HashSet<Location>,
+    start: Location,
+    end: Location,
+) -> bool {
+    use self::Prototerrain::*;
+
+    let weights = Dijkstra::new(
+        vec![end],
+        |&loc| match map.get(loc) {
+            Unused | Floor | Corridor | Door => true,
+            Wall => room_walls.contains(&loc),
+            _ => false,
+        },
+        10_000,
+    ).weights;
+
+    if !weights.contains_key(&start) {
+        return false;
+    }
+
+    let mut loc = start;
+    while loc != end {
+        // Walk downhill on the distance map. Dir6 iteration order is fixed, so ties get broken
+        // the same way every time.
+        loc = Dir6::iter()
+            .map(|&d| loc + d)
+            .filter(|n| weights.contains_key(n))
+            .min_by_key(|n| weights[n])
+            .expect("Broken corridor distance map");
+
+        match map.get(loc) {
+            Unused => map.set(loc, Corridor),
+            Wall => map.set(loc, Door),
+            _ => {}
+        }
+    }
+
+    true
+}
+
 fn screen_map(origin: Location) -> Field<Prototerrain> {
     let mut ret = Field::new();
 
@@ -336,6 +996,58 @@ fn screen_map(origin: Location) -> Field<Prototerrain> {
     ret
 }
 
+/// Return whether a cell can be the dead-end exit pocket for a path down to the next level.
+fn can_be_path_down_opening(map: &Field<Prototerrain>, origin: Location, loc: Location) -> bool {
+    use self::Prototerrain::*;
+    const MIN_EXIT_DISTANCE: i32 = 12;
+
+    loc.metric_distance(origin) > MIN_EXIT_DISTANCE && map.get(loc + Dir6::North) == Floor &&
+        map.get(loc + Dir6::Northeast) != Floor &&
+        map.get(loc + Dir6::Southeast) != Floor &&
+        map.get(loc + Dir6::South) != Floor &&
+        map.get(loc + Dir6::Southwest) != Floor &&
+        map.get(loc + Dir6::Northwest) != Floor
+}
+
+/// Dig a corridor southwards from the map floor until it makes a valid exit opening.
+///
+/// Fallback for maps where no natural exit site was found. Tries starting from the floor cells
+/// furthest away from the entrance first. Returns the exit location if successful.
+fn carve_exit(map: &mut Field<Prototerrain>, origin: Location) -> Option<Location> {
+    use self::Prototerrain::*;
+
+    let mut starts: Vec<(Location, u32)> =
+        Dijkstra::new(vec![origin], |&loc| map.get(loc).is_walkable(), 10_000)
+            .weights
+            .into_iter()
+            .collect();
+    // Furthest first, tie-break with location to keep things deterministic.
+    starts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    for (start, _) in starts {
+        let mut trial = map.clone();
+        let mut loc = start;
+
+        loop {
+            loc = loc + Dir6::South;
+
+            if can_be_path_down_opening(&trial, origin, loc) {
+                *map = trial;
+                return Some(loc);
+            }
+
+            match trial.get(loc) {
+                Floor | Corridor | Door | LockedDoor => {}
+                Unused => trial.set(loc, Floor),
+                // Ran into the map edge or a structure, try another start.
+                _ => break,
+            }
+        }
+    }
+
+    None
+}
+
 fn entry_cave_enclosure(map: &mut Field<Prototerrain>, entrance: Location) {
     use self::Prototerrain::*;
 
@@ -363,3 +1075,102 @@ fn entry_cave_enclosure(map: &mut Field<Prototerrain>, entrance: Location) {
     // Enclosure mouth
     map.set(entrance + vec2(1, 1), Floor);
 }
+
+#[cfg(test)]
+mod test {
+    use super::{LevelType, Maps, Worldgen};
+    use Rng;
+    use calx_grid::{Dijkstra, Dir6};
+    use euclid::vec2;
+    use item::ItemType;
+    use location::{Location, Sector};
+    use rand::SeedableRng;
+    use terrain::Terrain;
+
+    /// Return whether the level exit can be reached from the entrance.
+    ///
+    /// Locked doors can only be passed after picking up the key, so the key must be reachable
+    /// without going through them.
+    fn is_exit_reachable(maps: &Maps, entrance: Location, exit: Location) -> bool {
+        let walkable = |loc: &Location, with_key: bool| match maps.terrain.get(loc) {
+            Some(&Terrain::LockedDoor) => with_key,
+            Some(t) => !t.blocks_walk(),
+            None => false,
+        };
+
+        let without_key =
+            Dijkstra::new(vec![entrance], |loc| walkable(loc, false), 10_000).weights;
+        let key = maps.spawns.iter().find(|&&(_, ref loadout)| {
+            loadout.item.as_ref().map_or(false, |i| i.item_type == ItemType::Key)
+        });
+        let reachable = match key {
+            Some(&(key_loc, _)) if without_key.contains_key(&key_loc) => {
+                Dijkstra::new(vec![entrance], |loc| walkable(loc, true), 10_000).weights
+            }
+            Some(_) => return false,
+            None if maps.terrain.values().any(|&t| t == Terrain::LockedDoor) => return false,
+            None => without_key,
+        };
+
+        reachable.contains_key(&(exit + Dir6::North))
+    }
+
+    fn check_connectivity(level_type: LevelType, seeds: u32) {
+        for seed in 1..(seeds + 1) {
+            let mut maps = Maps::default();
+            let mut rng: Rng = SeedableRng::from_seed([seed, seed, seed, seed]);
+
+            let entrance = Location::new(0, 0, 1);
+            let exit = maps.gen_level(&mut rng, entrance, level_type);
+
+            assert!(
+                is_exit_reachable(&maps, entrance, exit),
+                "{:?} exit {:?} not reachable from entrance with seed {}",
+                level_type,
+                exit,
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn test_cave_connectivity() { check_connectivity(LevelType::Caves, 100); }
+
+    #[test]
+    fn test_room_connectivity() { check_connectivity(LevelType::Rooms, 100); }
+
+    #[test]
+    #[ignore]
+    fn test_connectivity_many_seeds() {
+        check_connectivity(LevelType::Caves, 2000);
+        check_connectivity(LevelType::Rooms, 2000);
+    }
+
+    #[test]
+    fn test_fallback_level() {
+        let mut maps = Maps::default();
+        let entrance = Location::new(0, 0, 1);
+        let exit = maps.gen_fallback_level(entrance);
+        assert!(is_exit_reachable(&maps, entrance, exit));
+    }
+
+    #[test]
+    fn test_stairs_up() {
+        // Like loading a save deep in a dungeon, nothing above has been generated yet.
+        let worldgen = Worldgen::new(1);
+        let start = Sector::new(0, 0, 3).origin();
+
+        let stairs = worldgen.get_portal(start - vec2(1, 1)).expect(
+            "No stairs up from level",
+        );
+        assert_eq!(stairs.z, 2);
+        assert_eq!(worldgen.get_portal(stairs + vec2(1, 1)), Some(start));
+
+        // Levels are stitched together with border portals, the surface has a hole in the ground.
+        let is_border = |loc| worldgen.get_map_portal(loc).map(|p| p.is_border());
+        assert_eq!(is_border(start - vec2(1, 1)), Some(true));
+        assert_eq!(is_border(stairs + vec2(1, 1)), Some(true));
+        let first = Sector::new(0, 0, 1).origin();
+        assert_eq!(is_border(first - vec2(1, 1)), Some(false));
+    }
+}